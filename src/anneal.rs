@@ -0,0 +1,234 @@
+use crate::approximator::{BlockApproximator, BlockCandidate, BlockRng};
+use crate::budget::Budget;
+use crate::distortion::Distortion;
+use crate::hash::BlockHasher;
+use crate::metric::BlockMetric;
+use clap::ValueEnum;
+use ndarray::prelude::*;
+use ndarray_rand::rand::Rng;
+use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::RandomExt;
+use std::fmt;
+use std::str::FromStr;
+
+/// How the temperature decreases from the start to the end temperature
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cooling {
+    /// The temperature is multiplied by a constant factor in every iteration
+    #[default]
+    Exponential,
+    /// The temperature decreases by a constant amount in every iteration
+    Linear,
+}
+
+/// Temperature schedule of the annealing search. Temperatures are given in
+/// units of the error metric per pixel, for the default metric the mean
+/// squared error, so they do not depend on the block size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemperatureSchedule {
+    pub start: f32,
+    pub end: f32,
+    pub cooling: Cooling,
+}
+
+impl TemperatureSchedule {
+    /// Whether both temperatures are positive, exponential cooling is not
+    /// defined otherwise
+    pub fn is_valid(&self) -> bool {
+        self.start > 0.0 && self.end > 0.0
+    }
+
+    /// Temperature after the given fraction (0 to 1) of the budget
+    pub fn temperature(&self, progress: f32) -> f32 {
+        match self.cooling {
+            Cooling::Exponential => self.start * (self.end / self.start).powf(progress),
+            Cooling::Linear => self.start + (self.end - self.start) * progress,
+        }
+    }
+}
+
+impl Default for TemperatureSchedule {
+    fn default() -> Self {
+        TemperatureSchedule {
+            start: 10000.0,
+            end: 100.0,
+            cooling: Cooling::default(),
+        }
+    }
+}
+
+/// Formats the schedule as `START:END:COOLING`, e.g. `10000:100:exponential`
+impl fmt::Display for TemperatureSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cooling = self.cooling.to_possible_value().unwrap();
+        write!(f, "{}:{}:{}", self.start, self.end, cooling.get_name())
+    }
+}
+
+impl FromStr for TemperatureSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let [start, end, cooling] = s.split(':').collect::<Vec<_>>()[..] else {
+            return Err(format!("expected START:END:COOLING, got '{s}'"));
+        };
+        let temperature = |value: &str| {
+            value
+                .parse()
+                .map_err(|_| format!("invalid temperature '{value}'"))
+        };
+        Ok(TemperatureSchedule {
+            start: temperature(start)?,
+            end: temperature(end)?,
+            cooling: Cooling::from_str(cooling, true)?,
+        })
+    }
+}
+
+/// Simulated annealing over the distortions of a block. Every iteration
+/// changes the distortion of a single pixel that the mask does not freeze,
+/// a block without such pixels is hashed once as it is. Worse candidates
+/// are accepted with a probability that shrinks as the temperature falls,
+/// the best candidate seen is returned.
+pub struct AnnealingApproximator {
+    hasher: Box<dyn BlockHasher>,
+    metric: Box<dyn BlockMetric>,
+    distortion: Distortion,
+    schedule: TemperatureSchedule,
+}
+
+impl AnnealingApproximator {
+    pub fn new(
+        hasher: Box<dyn BlockHasher>,
+        metric: Box<dyn BlockMetric>,
+        distortion: Distortion,
+        schedule: TemperatureSchedule,
+    ) -> Self {
+        AnnealingApproximator {
+            hasher,
+            metric,
+            distortion,
+            schedule,
+        }
+    }
+
+    fn evaluate(&self, source: &Array2<u8>, target: &ArrayView2<u8>, output: &mut [u8]) -> f32 {
+        self.hasher.hash(source.as_slice().unwrap(), output);
+        let current_target = ArrayView::from_shape(target.dim(), &*output).unwrap();
+        self.metric.error(target, &current_target)
+    }
+}
+
+impl BlockApproximator for AnnealingApproximator {
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        budget: &Budget,
+        rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let shape = input.dim();
+        let pixels = shape.0 * shape.1;
+        let mut output = vec![0; pixels];
+
+        let levels = self.distortion.levels();
+        let mut current_levels: Array2<u8> =
+            Array::random_using(shape, Uniform::new(0, levels), rng);
+        let mut current_source = self.distortion.apply_block(input, &current_levels, mask);
+        let mut current_error = self.evaluate(&current_source, target, &mut output);
+        let mut best_source = current_source.clone();
+        let mut best_target = Array::from_shape_vec(shape, output.clone()).unwrap();
+        let mut error = current_error;
+        let mut best_iteration = 1;
+
+        let movable: Vec<_> = mask
+            .indexed_iter()
+            .filter(|(_, &mask)| self.distortion.amplitude_at(mask) > 0)
+            .map(|(index, _)| index)
+            .collect();
+        let mut iteration = 1;
+        while !movable.is_empty() && !budget.exhausted(iteration) {
+            let temperature = self.schedule.temperature(budget.progress(iteration)) * pixels as f32;
+            iteration += 1;
+
+            // Move to a neighbour by changing the distortion of one pixel
+            let mut candidate = current_source.clone();
+            let index = movable[rng.gen_range(0..movable.len())];
+            let shift = rng.gen_range(1..levels);
+            let level =
+                ((u16::from(current_levels[index]) + u16::from(shift)) % u16::from(levels)) as u8;
+            candidate[index] = self.distortion.apply(input[index], level, mask[index]);
+
+            let candidate_error = self.evaluate(&candidate, target, &mut output);
+            let accept = candidate_error <= current_error
+                || rng.gen::<f32>() < ((current_error - candidate_error) / temperature).exp();
+            if candidate_error < error {
+                best_source = candidate.clone();
+                best_target = Array::from_shape_vec(shape, output.clone()).unwrap();
+                error = candidate_error;
+                best_iteration = iteration;
+            }
+            if accept {
+                current_levels[index] = level;
+                current_source = candidate;
+                current_error = candidate_error;
+            }
+        }
+        *counter += iteration;
+        BlockCandidate {
+            error,
+            iteration: best_iteration,
+            source: best_source,
+            target: best_target,
+            nonce: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approximator::block_rng;
+    use crate::distortion::DistortionRange;
+    use crate::hash::HashAlgorithm;
+    use crate::metric::ErrorMetric;
+
+    fn approximate(mask: Array2<u8>, counter: &mut u64) -> BlockCandidate {
+        let approximator = AnnealingApproximator::new(
+            HashAlgorithm::Shake256.hasher(),
+            ErrorMetric::default().metric(),
+            Distortion {
+                range: DistortionRange::Signed,
+                amplitude: 3,
+            },
+            TemperatureSchedule::default(),
+        );
+        approximator.approximate(
+            &array![[10, 20], [30, 40]].view(),
+            &Array2::zeros((2, 2)).view(),
+            &mask.view(),
+            &Budget::new(50, None),
+            &mut block_rng(0, 0, 0, 0),
+            counter,
+        )
+    }
+
+    #[test]
+    fn frozen_pixels_keep_their_value() {
+        let mut counter = 0;
+        let candidate = approximate(array![[0, 255], [0, 0]], &mut counter);
+        assert_eq!(counter, 50);
+        assert_eq!(candidate.source[(0, 0)], 10);
+        assert_eq!(candidate.source.slice(s![1, ..]), array![30, 40]);
+    }
+
+    #[test]
+    fn frozen_block_is_hashed_once() {
+        let mut counter = 0;
+        let candidate = approximate(Array2::zeros((2, 2)), &mut counter);
+        assert_eq!(counter, 1);
+        assert_eq!(candidate.source, array![[10, 20], [30, 40]]);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::budget::Budget;
+use crate::distortion::Distortion;
+use crate::hash::BlockHasher;
+use crate::metric::BlockMetric;
+use ndarray::prelude::*;
+use ndarray_rand::rand::SeedableRng;
+use ndarray_rand::rand_distr::Uniform;
+use ndarray_rand::RandomExt;
+use rand_chacha::ChaCha8Rng;
+
+/// Random number generator used for the search within a block
+pub type BlockRng = ChaCha8Rng;
+
+/// Derives the random number generator of the block at pixel position
+/// (`x`, `y`) of a colour channel. Every block gets its own stream of the
+/// seeded generator so the result does not depend on the order in which
+/// blocks are processed. The channel is mixed into the seed, channel 0 uses
+/// the seed as is.
+pub fn block_rng(seed: u64, channel: usize, x: u32, y: u32) -> BlockRng {
+    let seed = seed ^ (channel as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut rng = BlockRng::seed_from_u64(seed);
+    rng.set_stream((u64::from(x) << 32) | u64::from(y));
+    rng
+}
+
+/// The best distortion found for a block
+pub struct BlockCandidate {
+    pub error: f32,
+    /// Number of distortions tried in this search up to and including the
+    /// best one, 0 if the candidate was not found by the search
+    pub iteration: u64,
+    /// The distorted source block
+    pub source: Array2<u8>,
+    /// The hash of the distorted source block
+    pub target: Array2<u8>,
+    /// Nonce appended to the source block before hashing, if any
+    pub nonce: Option<u64>,
+}
+
+impl BlockCandidate {
+    /// Value of the counter when the candidate was found, given the counter
+    /// at the start of the search. 0 if the candidate was not found by the
+    /// search.
+    pub fn found_at(&self, counter: u64) -> u64 {
+        match self.iteration {
+            0 => 0,
+            iteration => counter + iteration,
+        }
+    }
+}
+
+pub trait BlockApproximator: Sync {
+    /// Approximates a block, the block size is given by the shape of `input`.
+    /// `mask` holds the distortion mask value of every pixel of the block.
+    /// The search stops when `budget` is exhausted. `counter` is the number
+    /// of distortions tried for the block so far, it is advanced by the
+    /// number of distortions tried in this call.
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        budget: &Budget,
+        rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate;
+
+    /// Recomputes the candidate that was tried when the counter had the
+    /// given value, so the best candidate of a previous run can be restored
+    /// from its counter. `None` if the candidates do not follow from the
+    /// counter.
+    fn replay(
+        &self,
+        _input: &ArrayView2<u8>,
+        _target: &ArrayView2<u8>,
+        _mask: &ArrayView2<u8>,
+        _counter: u64,
+    ) -> Option<BlockCandidate> {
+        None
+    }
+}
+
+/// Randomly distorts the source block and keeps the distortion whose hash
+/// is closest to the target block
+pub struct HashApproximator {
+    hasher: Box<dyn BlockHasher>,
+    metric: Box<dyn BlockMetric>,
+    distortion: Distortion,
+}
+
+impl HashApproximator {
+    pub fn new(
+        hasher: Box<dyn BlockHasher>,
+        metric: Box<dyn BlockMetric>,
+        distortion: Distortion,
+    ) -> Self {
+        HashApproximator {
+            hasher,
+            metric,
+            distortion,
+        }
+    }
+}
+
+impl BlockApproximator for HashApproximator {
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        budget: &Budget,
+        rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let shape = input.dim();
+        let mut best_source = Array2::<u8>::zeros(shape);
+        let mut best_target = Array2::<u8>::zeros(shape);
+        let mut output = vec![0; shape.0 * shape.1];
+
+        let mut error = f32::MAX;
+        let mut best_iteration = 0;
+
+        let mut iteration = 0;
+        while !budget.exhausted(iteration) {
+            iteration += 1;
+            let levels: Array2<u8> =
+                Array::random_using(shape, Uniform::new(0, self.distortion.levels()), rng);
+            let current_source = self.distortion.apply_block(input, &levels, mask);
+            let input_vec = current_source.as_slice().unwrap();
+            self.hasher.hash(input_vec, &mut output);
+
+            let current_target = ArrayView::from_shape(shape, &output).unwrap();
+            let total_error = self.metric.error(target, &current_target);
+
+            if error > total_error {
+                best_source = current_source;
+                best_target = current_target.to_owned();
+                error = total_error;
+                best_iteration = iteration;
+            }
+        }
+        *counter += iteration;
+        BlockCandidate {
+            error,
+            iteration: best_iteration,
+            source: best_source,
+            target: best_target,
+            nonce: None,
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Width and height of the image blocks that are hashed individually
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSize {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl BlockSize {
+    pub fn new(width: usize, height: usize) -> Self {
+        BlockSize { width, height }
+    }
+
+    /// Maps a digest length onto the most square block that holds exactly
+    /// that many pixels, e.g. 64 bytes -> 8x8, 48 bytes -> 8x6.
+    pub fn for_digest_len(digest_len: usize) -> Self {
+        let height = (1..=digest_len)
+            .filter(|rows| digest_len.is_multiple_of(*rows) && rows * rows <= digest_len)
+            .max()
+            .unwrap_or(1);
+        BlockSize::new(digest_len / height, height)
+    }
+
+    /// Number of pixels in a block
+    pub fn pixels(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// All blocks in an image of the given dimensions, column by column. With
+    /// `include_partial` the smaller blocks at the right and bottom edge are
+    /// included, otherwise only complete blocks are returned.
+    pub fn blocks(&self, dimensions: (u32, u32), include_partial: bool) -> Vec<Block> {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let count = |len: u32, size: u32| match include_partial {
+            true => len.div_ceil(size),
+            false => len / size,
+        };
+        (0..count(dimensions.0, width))
+            .flat_map(|i| (0..count(dimensions.1, height)).map(move |j| (i * width, j * height)))
+            .map(|(x, y)| Block {
+                x,
+                y,
+                width: width.min(dimensions.0 - x),
+                height: height.min(dimensions.1 - y),
+            })
+            .collect()
+    }
+}
+
+/// Position and size of a block within an image
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for BlockSize {
+    fn default() -> Self {
+        BlockSize::new(8, 8)
+    }
+}
+
+impl fmt::Display for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for BlockSize {
+    type Err = String;
+
+    /// Parses either `WIDTHxHEIGHT` or a single number for square blocks
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |v: &str| match v.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!("invalid block size '{s}'")),
+            Ok(v) => Ok(v),
+        };
+        match s.split_once(['x', 'X']) {
+            Some((width, height)) => Ok(BlockSize::new(parse(width)?, parse(height)?)),
+            None => {
+                let size = parse(s)?;
+                Ok(BlockSize::new(size, size))
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::{Duration, Instant};
+
+/// Limits the search within a block by a number of iterations and
+/// optionally by a deadline
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    iterations: u64,
+    start: Instant,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// A budget that starts now
+    pub fn new(iterations: u64, deadline: Option<Instant>) -> Self {
+        Budget {
+            iterations,
+            start: Instant::now(),
+            deadline,
+        }
+    }
+
+    /// Whether the search has to stop after `iteration` iterations. The
+    /// first iteration is always allowed so that every block has a result.
+    pub fn exhausted(&self, iteration: u64) -> bool {
+        iteration > 0
+            && (iteration >= self.iterations
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline))
+    }
+
+    /// Fraction (0 to 1) of the budget used after `iteration` iterations,
+    /// whichever of the iterations and the time runs out first
+    pub fn progress(&self, iteration: u64) -> f32 {
+        let by_iterations = iteration as f32 / self.iterations as f32;
+        let by_time = self.deadline.map_or(0.0, |deadline| {
+            let total = deadline.saturating_duration_since(self.start);
+            if total.is_zero() {
+                1.0
+            } else {
+                self.start.elapsed().as_secs_f32() / total.as_secs_f32()
+            }
+        });
+        by_iterations.max(by_time).min(1.0)
+    }
+}
+
+/// Time limits of a run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeBudget {
+    /// Wall clock time of the whole run
+    pub total: Option<Duration>,
+    /// Time spent on a single block
+    pub block: Option<Duration>,
+}
+
+impl TimeBudget {
+    /// Time a single block may take when `blocks` blocks share the total
+    /// budget on `threads` threads
+    pub fn per_block(&self, blocks: usize, threads: usize) -> Option<Duration> {
+        let share = self
+            .total
+            .map(|total| total.mul_f64(threads as f64 / blocks.max(1) as f64));
+        match (share, self.block) {
+            (Some(share), Some(block)) => Some(share.min(block)),
+            (share, block) => share.or(block),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Checkpoints that allow an interrupted run to be resumed. A checkpoint is
+//! a text file with a header of the seed and the [`CheckpointSettings`], one
+//! `name value` pair per line, and one finished block per line:
+//! `channel x y error counter found rng nonce source target`, where `found`
+//! is the counter at which the best candidate was found, `rng` is the
+//! word position of the block's random number generator, `nonce` is `-`
+//! outside of nonce mode and `source` and `target` are the pixels of the best
+//! candidate in hex.
+
+use crate::anneal::TemperatureSchedule;
+use crate::block::BlockSize;
+use crate::color::ColorMode;
+use crate::distortion::Distortion;
+use crate::edge::EdgePolicy;
+use crate::engine::{Perturbation, SearchStrategy};
+use crate::error::{Error, Result};
+use crate::hash::HashAlgorithm;
+use crate::mask::FrozenBlocks;
+use crate::metric::ErrorMetric;
+use crate::preprocess::TargetPreprocessing;
+use crate::sidecar::BlockKey;
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Search state of a finished block
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockState {
+    pub error: f32,
+    pub counter: u64,
+    /// Value of the counter when the best candidate was found
+    pub found_at: u64,
+    /// Word position of the random number generator of the block
+    pub word_pos: u128,
+    pub nonce: Option<u64>,
+    /// Pixels of the best distorted source block, row by row
+    pub source: Vec<u8>,
+    /// Pixels of the hash of the best source block, row by row
+    pub target: Vec<u8>,
+}
+
+/// BLAKE3 digest of the planes of an image
+pub type ImageDigest = [u8; 32];
+
+/// Settings and inputs of a run that determine the blocks and the
+/// candidates a block search can find. A checkpoint can only be resumed
+/// with the same settings and inputs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CheckpointSettings {
+    pub hash: HashAlgorithm,
+    pub block_size: BlockSize,
+    pub color_mode: ColorMode,
+    pub edge_policy: EdgePolicy,
+    pub strategy: SearchStrategy,
+    pub temperature_schedule: TemperatureSchedule,
+    pub perturbation: Perturbation,
+    pub distortion: Distortion,
+    pub frozen_blocks: FrozenBlocks,
+    pub metric: ErrorMetric,
+    pub target_preprocessing: TargetPreprocessing,
+    /// Digest of the source planes after the edge policy and the
+    /// preparation for the distortion
+    pub source: ImageDigest,
+    /// Digest of the target planes after preprocessing and the edge policy
+    pub target: ImageDigest,
+    /// Digest of the distortion mask after the edge policy
+    pub mask: ImageDigest,
+}
+
+/// Digest of the planes of an image, including their dimensions
+pub fn digest<'a>(planes: impl IntoIterator<Item = &'a GrayscaleImage>) -> ImageDigest {
+    let mut hasher = blake3::Hasher::new();
+    for plane in planes {
+        let (width, height) = plane.dimensions();
+        hasher.update(&width.to_le_bytes());
+        hasher.update(&height.to_le_bytes());
+        hasher.update(plane.as_raw());
+    }
+    *hasher.finalize().as_bytes()
+}
+
+/// Progress of a run, written at intervals while the blocks are searched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub seed: u64,
+    pub settings: CheckpointSettings,
+    pub blocks: HashMap<BlockKey, BlockState>,
+}
+
+impl Checkpoint {
+    pub fn new(seed: u64, settings: CheckpointSettings) -> Self {
+        Checkpoint {
+            seed,
+            settings,
+            blocks: HashMap::new(),
+        }
+    }
+
+    /// Reads a checkpoint written by [`Checkpoint::write`]
+    pub fn read(path: &Path) -> Result<Checkpoint> {
+        let invalid_data =
+            |message: String| Error::io(path, io::Error::new(io::ErrorKind::InvalidData, message));
+        let invalid = |line: &str| invalid_data(format!("invalid line '{line}'"));
+        let mut header = HashMap::new();
+        let mut blocks = HashMap::new();
+        let content = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        for line in content.lines() {
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[..] {
+                [name, value] => {
+                    header.insert(name, value);
+                }
+                [channel, x, y, error, counter, found_at, word_pos, nonce, source, target] => {
+                    let parse = || -> Option<(BlockKey, BlockState)> {
+                        let key = (channel.parse().ok()?, x.parse().ok()?, y.parse().ok()?);
+                        let state = BlockState {
+                            error: error.parse().ok()?,
+                            counter: counter.parse().ok()?,
+                            found_at: found_at.parse().ok()?,
+                            word_pos: word_pos.parse().ok()?,
+                            nonce: match nonce {
+                                "-" => None,
+                                nonce => Some(nonce.parse().ok()?),
+                            },
+                            source: from_hex(source)?,
+                            target: from_hex(target)?,
+                        };
+                        Some((key, state))
+                    };
+                    let (key, state) = parse().ok_or_else(|| invalid(line))?;
+                    blocks.insert(key, state);
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        let settings = CheckpointSettings {
+            hash: field(&header, "hash", parse_value).map_err(&invalid_data)?,
+            block_size: field(&header, "block-size", parse_str).map_err(&invalid_data)?,
+            color_mode: field(&header, "color", parse_value).map_err(&invalid_data)?,
+            edge_policy: field(&header, "edge", parse_value).map_err(&invalid_data)?,
+            strategy: field(&header, "strategy", parse_value).map_err(&invalid_data)?,
+            temperature_schedule: field(&header, "temperature", parse_str)
+                .map_err(&invalid_data)?,
+            perturbation: field(&header, "perturb", parse_value).map_err(&invalid_data)?,
+            distortion: field(&header, "distortion", parse_str).map_err(&invalid_data)?,
+            frozen_blocks: field(&header, "frozen-blocks", parse_value).map_err(&invalid_data)?,
+            metric: field(&header, "metric", parse_value).map_err(&invalid_data)?,
+            target_preprocessing: TargetPreprocessing {
+                fit: field(&header, "fit", parse_value).map_err(&invalid_data)?,
+                stretch_contrast: field(&header, "stretch-contrast", parse_str)
+                    .map_err(&invalid_data)?,
+                equalize: field(&header, "equalize", parse_str).map_err(&invalid_data)?,
+            },
+            source: field(&header, "source", parse_digest).map_err(&invalid_data)?,
+            target: field(&header, "target", parse_digest).map_err(&invalid_data)?,
+            mask: field(&header, "mask", parse_digest).map_err(&invalid_data)?,
+        };
+        Ok(Checkpoint {
+            seed: field(&header, "seed", parse_str).map_err(&invalid_data)?,
+            settings,
+            blocks,
+        })
+    }
+
+    /// Writes the checkpoint. The file is replaced atomically, so a crash
+    /// while writing leaves the previous checkpoint intact.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let settings = &self.settings;
+        let preprocessing = &settings.target_preprocessing;
+        let header = [
+            ("seed", self.seed.to_string()),
+            ("hash", value_name(&settings.hash)),
+            ("block-size", settings.block_size.to_string()),
+            ("color", value_name(&settings.color_mode)),
+            ("edge", value_name(&settings.edge_policy)),
+            ("strategy", value_name(&settings.strategy)),
+            ("temperature", settings.temperature_schedule.to_string()),
+            ("perturb", value_name(&settings.perturbation)),
+            ("distortion", settings.distortion.to_string()),
+            ("frozen-blocks", value_name(&settings.frozen_blocks)),
+            ("metric", value_name(&settings.metric)),
+            ("fit", value_name(&preprocessing.fit)),
+            (
+                "stretch-contrast",
+                preprocessing.stretch_contrast.to_string(),
+            ),
+            ("equalize", preprocessing.equalize.to_string()),
+            ("source", to_hex(&settings.source)),
+            ("target", to_hex(&settings.target)),
+            ("mask", to_hex(&settings.mask)),
+        ];
+        let mut content = "# hash_art checkpoint\n".to_string();
+        for (name, value) in header {
+            content += &format!("{name} {value}\n");
+        }
+        content += "# channel x y error counter found rng nonce source target\n";
+        let mut keys: Vec<_> = self.blocks.keys().collect();
+        keys.sort();
+        for key @ (channel, x, y) in keys {
+            let state = &self.blocks[key];
+            let nonce = state
+                .nonce
+                .map_or_else(|| "-".to_string(), |nonce| nonce.to_string());
+            content += &format!(
+                "{channel} {x} {y} {} {} {} {} {nonce} {} {}\n",
+                state.error,
+                state.counter,
+                state.found_at,
+                state.word_pos,
+                to_hex(&state.source),
+                to_hex(&state.target)
+            );
+        }
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, content).map_err(|e| Error::io(path, e))?;
+        fs::rename(&temporary, path).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// Parses a field of the header
+fn field<T>(
+    header: &HashMap<&str, &str>,
+    name: &str,
+    parse: fn(&str) -> Option<T>,
+) -> std::result::Result<T, String> {
+    let value = header.get(name).ok_or_else(|| format!("missing {name}"))?;
+    parse(value).ok_or_else(|| format!("invalid {name} '{value}'"))
+}
+
+fn parse_str<T: FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+fn parse_digest(value: &str) -> Option<ImageDigest> {
+    from_hex(value)?.try_into().ok()
+}
+
+/// Name of a setting as it is given on the command line
+pub(crate) fn value_name<T: ValueEnum>(value: &T) -> String {
+    value.to_possible_value().unwrap().get_name().to_string()
+}
+
+fn parse_value<T: ValueEnum>(value: &str) -> Option<T> {
+    T::from_str(value, false).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Collects the state of finished blocks and writes a checkpoint whenever
+/// the interval has passed
+pub(crate) struct CheckpointWriter {
+    path: PathBuf,
+    interval: Duration,
+    state: Mutex<(Instant, Checkpoint)>,
+}
+
+impl CheckpointWriter {
+    pub(crate) fn new(path: PathBuf, interval: Duration, checkpoint: Checkpoint) -> Self {
+        CheckpointWriter {
+            path,
+            interval,
+            state: Mutex::new((Instant::now(), checkpoint)),
+        }
+    }
+
+    /// Records a finished block. Errors while writing are ignored, the
+    /// checkpoint is written again at the next interval.
+    pub(crate) fn update(&self, key: BlockKey, block: BlockState) {
+        let mut state = self.state.lock().unwrap();
+        let (written, checkpoint) = &mut *state;
+        checkpoint.blocks.insert(key, block);
+        if written.elapsed() >= self.interval && checkpoint.write(&self.path).is_ok() {
+            *written = Instant::now();
+        }
+    }
+
+    pub(crate) fn write(&self) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.1.write(&self.path)?;
+        state.0 = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anneal::Cooling;
+    use crate::distortion::DistortionRange;
+    use crate::preprocess::Fit;
+
+    #[test]
+    fn write_read_round_trip() {
+        let settings = CheckpointSettings {
+            hash: HashAlgorithm::Blake3Xof,
+            block_size: BlockSize {
+                width: 3,
+                height: 2,
+            },
+            color_mode: ColorMode::Rgb,
+            edge_policy: EdgePolicy::PadZero,
+            strategy: SearchStrategy::Enumerate,
+            temperature_schedule: TemperatureSchedule {
+                start: 0.1,
+                end: 1e-7,
+                cooling: Cooling::Linear,
+            },
+            perturbation: Perturbation::Nonce,
+            distortion: Distortion {
+                range: DistortionRange::Signed,
+                amplitude: 3,
+            },
+            frozen_blocks: FrozenBlocks::Skip,
+            metric: ErrorMetric::Ssim,
+            target_preprocessing: TargetPreprocessing {
+                fit: Fit::Letterbox,
+                stretch_contrast: true,
+                equalize: false,
+            },
+            source: digest([&GrayscaleImage::new(3, 2)]),
+            target: [0xab; 32],
+            mask: [0; 32],
+        };
+        let mut checkpoint = Checkpoint::new(u64::MAX, settings);
+        checkpoint.blocks.insert(
+            (2, 3, 4),
+            BlockState {
+                error: 1234.5,
+                counter: 100,
+                found_at: 42,
+                word_pos: u128::from(u64::MAX) + 7,
+                nonce: Some(41),
+                source: vec![0, 1, 15, 16, 254, 255],
+                target: vec![255, 128, 0, 7, 8, 9],
+            },
+        );
+        checkpoint.blocks.insert(
+            (0, 0, 0),
+            BlockState {
+                error: 0.0,
+                counter: 0,
+                found_at: 0,
+                word_pos: 0,
+                nonce: None,
+                source: vec![10; 6],
+                target: vec![20; 6],
+            },
+        );
+        let path = std::env::temp_dir().join(format!("hash_art_{}.checkpoint", std::process::id()));
+        checkpoint.write(&path).unwrap();
+        let read = Checkpoint::read(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), checkpoint);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(from_hex(&to_hex(&bytes)), Some(bytes));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+}
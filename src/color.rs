@@ -0,0 +1,53 @@
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::{DynamicImage, ImageBuffer, Luma, Rgb, RgbImage};
+
+/// Whether images are processed in grayscale or per colour channel
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Images are converted to grayscale
+    #[default]
+    Gray,
+    /// The red, green and blue planes are distorted and hashed independently
+    Rgb,
+}
+
+impl ColorMode {
+    /// Number of planes an image is split into
+    pub fn channels(self) -> usize {
+        match self {
+            ColorMode::Gray => 1,
+            ColorMode::Rgb => 3,
+        }
+    }
+
+    /// Splits an image into the planes that are hashed independently
+    pub fn split(self, image: &DynamicImage) -> Vec<GrayscaleImage> {
+        match self {
+            ColorMode::Gray => vec![image.to_luma8()],
+            ColorMode::Rgb => {
+                let image = image.to_rgb8();
+                (0..3)
+                    .map(|channel| {
+                        ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                            Luma([image.get_pixel(x, y)[channel]])
+                        })
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Merges the planes created by [`ColorMode::split`] back into an image
+    pub fn merge(self, mut planes: Vec<GrayscaleImage>) -> DynamicImage {
+        match self {
+            ColorMode::Gray => DynamicImage::ImageLuma8(planes.swap_remove(0)),
+            ColorMode::Rgb => {
+                let (width, height) = planes[0].dimensions();
+                DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+                    Rgb([0, 1, 2].map(|channel| planes[channel].get_pixel(x, y)[0]))
+                }))
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Diagnostic images to judge the quality of a run at a glance
+
+use crate::block::BlockSize;
+use crate::color::ColorMode;
+use crate::engine::Approximation;
+use crate::GrayscaleImage;
+use image::{imageops, DynamicImage, GenericImageView, Luma, Rgb, RgbImage};
+
+/// Colours every block by its error, from black for the lowest to white for
+/// the highest error of the run. The errors of the planes of a block are
+/// added up. Pixels that are not part of a block stay black.
+pub fn error_heatmap(approximation: &Approximation, block_size: BlockSize) -> RgbImage {
+    let (width, height) = approximation.source.dimensions();
+    let columns = (width as usize).div_ceil(block_size.width);
+    let rows = (height as usize).div_ceil(block_size.height);
+    let mut errors = vec![None; columns * rows];
+    for block in &approximation.blocks {
+        let cell =
+            (block.y as usize / block_size.height) * columns + block.x as usize / block_size.width;
+        *errors[cell].get_or_insert(0.0) += block.error;
+    }
+    let (min, max) = errors
+        .iter()
+        .flatten()
+        .fold((f32::MAX, f32::MIN), |(min, max), &error| {
+            (min.min(error), max.max(error))
+        });
+    RgbImage::from_fn(width, height, |x, y| {
+        let cell = (y as usize / block_size.height) * columns + x as usize / block_size.width;
+        match errors[cell] {
+            Some(error) if max > min => heat((error - min) / (max - min)),
+            Some(_) => heat(0.0),
+            None => Rgb([0, 0, 0]),
+        }
+    })
+}
+
+/// Maps 0 to 1 onto black, red, yellow and white
+fn heat(value: f32) -> Rgb<u8> {
+    let channel = |offset: f32| ((value * 3.0 - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Rgb([channel(0.0), channel(1.0), channel(2.0)])
+}
+
+/// Absolute difference between the source and the result source, per plane
+/// of the colour mode. The distortions are small, so the differences are
+/// stretched so that the largest one is white.
+pub fn difference(
+    source: &DynamicImage,
+    result_source: &DynamicImage,
+    color_mode: ColorMode,
+) -> DynamicImage {
+    let (width, height) = result_source.dimensions();
+    let sources = color_mode.split(source);
+    let results = color_mode.split(result_source);
+    let differences: Vec<GrayscaleImage> = sources
+        .iter()
+        .zip(&results)
+        .map(|(source, result)| {
+            GrayscaleImage::from_fn(width, height, |x, y| {
+                let difference = if x < source.width() && y < source.height() {
+                    source.get_pixel(x, y)[0].abs_diff(result.get_pixel(x, y)[0])
+                } else {
+                    0
+                };
+                Luma([difference])
+            })
+        })
+        .collect();
+    let max = differences
+        .iter()
+        .flat_map(|plane| plane.pixels())
+        .map(|pixel| pixel[0])
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let stretched = differences
+        .into_iter()
+        .map(|plane| {
+            GrayscaleImage::from_fn(width, height, |x, y| {
+                Luma([(plane.get_pixel(x, y)[0] as u32 * 255 / max as u32) as u8])
+            })
+        })
+        .collect();
+    color_mode.merge(stretched)
+}
+
+/// Peak signal-to-noise ratio of the result source compared with the
+/// source in dB, over the planes of the colour mode and the pixels both
+/// images have. Infinite if the images are identical.
+pub fn psnr(source: &DynamicImage, result_source: &DynamicImage, color_mode: ColorMode) -> f64 {
+    let sources = color_mode.split(source);
+    let results = color_mode.split(result_source);
+    let (mut squared_error, mut pixels) = (0.0, 0u64);
+    for (source, result) in sources.iter().zip(&results) {
+        let width = source.width().min(result.width());
+        let height = source.height().min(result.height());
+        for y in 0..height {
+            for x in 0..width {
+                let difference =
+                    f64::from(source.get_pixel(x, y)[0]) - f64::from(result.get_pixel(x, y)[0]);
+                squared_error += difference * difference;
+            }
+        }
+        pixels += u64::from(width) * u64::from(height);
+    }
+    let mean_squared_error = squared_error / pixels.max(1) as f64;
+    10.0 * (255.0 * 255.0 / mean_squared_error).log10()
+}
+
+/// Four panels: source and result source on top, target and result target
+/// below
+pub fn composite(
+    source: &DynamicImage,
+    result_source: &DynamicImage,
+    target: &DynamicImage,
+    result_target: &DynamicImage,
+) -> RgbImage {
+    let panels = [source, result_source, target, result_target];
+    let width = panels.iter().map(|panel| panel.width()).max().unwrap_or(0);
+    let height = panels.iter().map(|panel| panel.height()).max().unwrap_or(0);
+    let mut composite = RgbImage::new(2 * width, 2 * height);
+    for (i, panel) in panels.iter().enumerate() {
+        let (x, y) = ((i as u32 % 2) * width, (i as u32 / 2) * height);
+        imageops::replace(&mut composite, &panel.to_rgb8(), x.into(), y.into());
+    }
+    composite
+}
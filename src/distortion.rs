@@ -0,0 +1,171 @@
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::Luma;
+use imageproc::map::map_colors2;
+use ndarray::prelude::*;
+use ndarray::Zip;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// How the distortion of a pixel relates to its original value
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DistortionRange {
+    /// 0 to the amplitude is added. The source is darkened by the amplitude
+    /// beforehand so that no pixel overflows.
+    #[default]
+    Additive,
+    /// -amplitude to +amplitude is added around the original pixel, clamped
+    /// to the valid pixel range
+    Signed,
+    /// The lowest bits of the pixel are replaced, the amplitude is the number
+    /// of bits. A pixel changes by at most 2^bits - 1 and the levels are the
+    /// bit patterns.
+    Lsb,
+}
+
+/// The distortions a single pixel can receive. A distortion is given by its
+/// level, an index from 0 to [`Distortion::levels`] (exclusive), which the
+/// search strategies draw or enumerate. The change of every pixel is scaled
+/// by its value in the distortion mask, 0 freezes the pixel and 255 allows
+/// the full amplitude.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Distortion {
+    pub range: DistortionRange,
+    /// Largest change of a pixel, 1 to [`Distortion::MAX_AMPLITUDE`]. For
+    /// [`DistortionRange::Lsb`] the number of replaced bits, 1 to
+    /// [`Distortion::MAX_BITS`].
+    pub amplitude: u8,
+}
+
+impl Distortion {
+    pub const MAX_AMPLITUDE: u8 = 127;
+    pub const MAX_BITS: u8 = 7;
+
+    /// Largest valid amplitude of the range
+    pub fn max_amplitude(&self) -> u8 {
+        match self.range {
+            DistortionRange::Additive | DistortionRange::Signed => Distortion::MAX_AMPLITUDE,
+            DistortionRange::Lsb => Distortion::MAX_BITS,
+        }
+    }
+
+    /// Number of distinct distortions of a pixel
+    pub fn levels(&self) -> u8 {
+        match self.range {
+            DistortionRange::Additive => self.amplitude + 1,
+            DistortionRange::Signed => 2 * self.amplitude + 1,
+            DistortionRange::Lsb => 1 << self.amplitude,
+        }
+    }
+
+    /// The levels that give the pixel distinct values with the full mask.
+    /// Clamping to the valid pixel range maps the other levels to the same
+    /// value as the first or last of them.
+    pub fn distinct_levels(&self, pixel: u8) -> Range<u8> {
+        let amplitude = u16::from(self.amplitude);
+        let headroom = 255 - u16::from(pixel);
+        match self.range {
+            DistortionRange::Additive => 0..amplitude.min(headroom) as u8 + 1,
+            DistortionRange::Signed => {
+                let first = amplitude.saturating_sub(u16::from(pixel));
+                first as u8..(2 * amplitude).min(headroom + amplitude) as u8 + 1
+            }
+            DistortionRange::Lsb => 0..self.levels(),
+        }
+    }
+
+    /// Amplitude of a pixel with the given mask value
+    pub fn amplitude_at(&self, mask: u8) -> u8 {
+        scale(i16::from(self.amplitude), mask) as u8
+    }
+
+    /// The distortion of a pixel with the given mask value. Its levels are
+    /// the distinct distortions of the pixel when applied with the full mask.
+    pub fn masked(&self, mask: u8) -> Distortion {
+        Distortion {
+            range: self.range,
+            amplitude: self.amplitude_at(mask),
+        }
+    }
+
+    /// Distorts a pixel of the prepared source
+    pub fn apply(&self, pixel: u8, level: u8, mask: u8) -> u8 {
+        let delta = match self.range {
+            DistortionRange::Additive => i16::from(level),
+            DistortionRange::Signed => i16::from(level) - i16::from(self.amplitude),
+            DistortionRange::Lsb => {
+                // The mask reduces the number of replaced bits
+                let low_bits = (1 << self.amplitude_at(mask)) - 1;
+                return (pixel & !low_bits) | (level & low_bits);
+            }
+        };
+        (i16::from(pixel) + scale(delta, mask)).clamp(0, 255) as u8
+    }
+
+    /// Distorts every pixel of a block by its level
+    pub fn apply_block(
+        &self,
+        input: &ArrayView2<u8>,
+        levels: &Array2<u8>,
+        mask: &ArrayView2<u8>,
+    ) -> Array2<u8> {
+        Zip::from(input)
+            .and(levels)
+            .and(mask)
+            .map_collect(|&pixel, &level, &mask| self.apply(pixel, level, mask))
+    }
+
+    /// Whether the mask leaves no pixel of a block any distortion
+    pub fn is_frozen(&self, mask: &ArrayView2<u8>) -> bool {
+        mask.iter().all(|&mask| self.amplitude_at(mask) == 0)
+    }
+
+    /// Prepares a plane of the source before its blocks are distorted
+    pub fn prepare(&self, source: &GrayscaleImage, mask: &GrayscaleImage) -> GrayscaleImage {
+        match self.range {
+            DistortionRange::Additive => map_colors2(source, mask, |p, mask| {
+                Luma([p[0].saturating_sub(self.amplitude_at(mask[0]))])
+            }),
+            DistortionRange::Signed | DistortionRange::Lsb => source.clone(),
+        }
+    }
+}
+
+/// Scales a change of a pixel by its mask value
+fn scale(delta: i16, mask: u8) -> i16 {
+    (f32::from(delta) * f32::from(mask) / 255.0).round() as i16
+}
+
+impl Default for Distortion {
+    fn default() -> Self {
+        Distortion {
+            range: DistortionRange::default(),
+            amplitude: 1,
+        }
+    }
+}
+
+/// Formats the distortion as `RANGE:AMPLITUDE`, e.g. `signed:2`
+impl fmt::Display for Distortion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = self.range.to_possible_value().unwrap();
+        write!(f, "{}:{}", range.get_name(), self.amplitude)
+    }
+}
+
+impl FromStr for Distortion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, amplitude) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected RANGE:AMPLITUDE, got '{s}'"))?;
+        Ok(Distortion {
+            range: DistortionRange::from_str(range, true)?,
+            amplitude: amplitude
+                .parse()
+                .map_err(|_| format!("invalid amplitude '{amplitude}'"))?,
+        })
+    }
+}
@@ -0,0 +1,176 @@
+use crate::block::{Block, BlockSize};
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::{ImageBuffer, Luma};
+
+/// How pixels at the right and bottom edge that do not fill a complete block
+/// are handled
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EdgePolicy {
+    /// Process the smaller edge blocks with a truncated digest
+    #[default]
+    Partial,
+    /// Crop the images to a multiple of the block size
+    Crop,
+    /// Pad the images to a multiple of the block size by repeating the last
+    /// row and column
+    PadReplicate,
+    /// Pad the images to a multiple of the block size with black pixels
+    PadZero,
+    /// Leave the remaining pixels undistorted
+    Skip,
+}
+
+impl EdgePolicy {
+    /// Crops or pads the image as required by the policy
+    pub fn prepare(self, image: &GrayscaleImage, block_size: BlockSize) -> GrayscaleImage {
+        let (width, height) = image.dimensions();
+        let (block_width, block_height) = (block_size.width as u32, block_size.height as u32);
+        match self {
+            EdgePolicy::Partial | EdgePolicy::Skip => image.clone(),
+            EdgePolicy::Crop => image::imageops::crop_imm(
+                image,
+                0,
+                0,
+                width / block_width * block_width,
+                height / block_height * block_height,
+            )
+            .to_image(),
+            EdgePolicy::PadReplicate | EdgePolicy::PadZero => {
+                let padded_width = width.div_ceil(block_width) * block_width;
+                let padded_height = height.div_ceil(block_height) * block_height;
+                ImageBuffer::from_fn(padded_width, padded_height, |x, y| {
+                    if x < width && y < height {
+                        *image.get_pixel(x, y)
+                    } else if self == EdgePolicy::PadZero {
+                        Luma([0])
+                    } else {
+                        *image.get_pixel(x.min(width - 1), y.min(height - 1))
+                    }
+                })
+            }
+        }
+    }
+
+    /// Prepares the distortion mask like [`EdgePolicy::prepare`]. With
+    /// [`EdgePolicy::Skip`] the pixels outside of the blocks are masked out,
+    /// so they keep their original value.
+    pub fn prepare_mask(self, mask: &GrayscaleImage, block_size: BlockSize) -> GrayscaleImage {
+        let mut mask = self.prepare(mask, block_size);
+        if self == EdgePolicy::Skip {
+            let (width, height) = mask.dimensions();
+            let (block_width, block_height) = (block_size.width as u32, block_size.height as u32);
+            let (covered_width, covered_height) = (
+                width / block_width * block_width,
+                height / block_height * block_height,
+            );
+            for (x, y, pixel) in mask.enumerate_pixels_mut() {
+                if x >= covered_width || y >= covered_height {
+                    *pixel = Luma([0]);
+                }
+            }
+        }
+        mask
+    }
+
+    /// The blocks that are processed in an image prepared with this policy
+    pub fn blocks(self, dimensions: (u32, u32), block_size: BlockSize) -> Vec<Block> {
+        block_size.blocks(dimensions, self == EdgePolicy::Partial)
+    }
+
+    /// Describes what the policy does to an image of the given dimensions
+    pub fn describe(self, dimensions: (u32, u32), block_size: BlockSize) -> String {
+        let (width, height) = dimensions;
+        let (block_width, block_height) = (block_size.width as u32, block_size.height as u32);
+        let (rest_x, rest_y) = (width % block_width, height % block_height);
+        if rest_x == 0 && rest_y == 0 {
+            return format!("image dimensions are a multiple of the block size {block_size}");
+        }
+        match self {
+            EdgePolicy::Partial => format!(
+                "edge blocks of {rest_x} columns and {rest_y} rows are hashed with a truncated digest"
+            ),
+            EdgePolicy::Crop => format!(
+                "cropped from {width}x{height} to {}x{}",
+                width - rest_x,
+                height - rest_y
+            ),
+            EdgePolicy::PadReplicate | EdgePolicy::PadZero => format!(
+                "padded from {width}x{height} to {}x{}",
+                width.div_ceil(block_width) * block_width,
+                height.div_ceil(block_height) * block_height
+            ),
+            EdgePolicy::Skip => format!(
+                "{rest_x} columns and {rest_y} rows at the edge are left undistorted"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dimensions of the prepared 5x3 image and its blocks for 2x2 blocks
+    fn layout(policy: EdgePolicy) -> ((u32, u32), Vec<Block>) {
+        let block_size = BlockSize::new(2, 2);
+        let image = policy.prepare(&GrayscaleImage::new(5, 3), block_size);
+        let blocks = policy.blocks(image.dimensions(), block_size);
+        (image.dimensions(), blocks)
+    }
+
+    fn blocks(blocks: &[(u32, u32, u32, u32)]) -> Vec<Block> {
+        blocks
+            .iter()
+            .map(|&(x, y, width, height)| Block {
+                x,
+                y,
+                width,
+                height,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_layout() {
+        let complete = blocks(&[(0, 0, 2, 2), (2, 0, 2, 2)]);
+        let padded = blocks(&[
+            (0, 0, 2, 2),
+            (0, 2, 2, 2),
+            (2, 0, 2, 2),
+            (2, 2, 2, 2),
+            (4, 0, 2, 2),
+            (4, 2, 2, 2),
+        ]);
+        let partial = blocks(&[
+            (0, 0, 2, 2),
+            (0, 2, 2, 1),
+            (2, 0, 2, 2),
+            (2, 2, 2, 1),
+            (4, 0, 1, 2),
+            (4, 2, 1, 1),
+        ]);
+        assert_eq!(layout(EdgePolicy::Partial), ((5, 3), partial));
+        assert_eq!(layout(EdgePolicy::Crop), ((4, 2), complete.clone()));
+        assert_eq!(layout(EdgePolicy::PadReplicate), ((6, 4), padded.clone()));
+        assert_eq!(layout(EdgePolicy::PadZero), ((6, 4), padded));
+        assert_eq!(layout(EdgePolicy::Skip), ((5, 3), complete));
+    }
+
+    #[test]
+    fn padding() {
+        let image = GrayscaleImage::from_fn(3, 1, |x, _| Luma([x as u8 + 1]));
+        let block_size = BlockSize::new(2, 2);
+        let replicated = EdgePolicy::PadReplicate.prepare(&image, block_size);
+        assert_eq!(replicated.as_raw(), &[1, 2, 3, 3, 1, 2, 3, 3]);
+        let zero = EdgePolicy::PadZero.prepare(&image, block_size);
+        assert_eq!(zero.as_raw(), &[1, 2, 3, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn skip_masks_out_the_edge() {
+        let mask = GrayscaleImage::from_pixel(3, 3, Luma([255]));
+        let prepared = EdgePolicy::Skip.prepare_mask(&mask, BlockSize::new(2, 2));
+        assert_eq!(prepared.as_raw(), &[255, 255, 0, 255, 255, 0, 0, 0, 0]);
+    }
+}
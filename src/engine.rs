@@ -0,0 +1,1067 @@
+use crate::anneal::{AnnealingApproximator, TemperatureSchedule};
+use crate::approximator::{
+    block_rng, BlockApproximator, BlockCandidate, BlockRng, HashApproximator,
+};
+use crate::block::{Block, BlockSize};
+use crate::budget::{Budget, TimeBudget};
+use crate::checkpoint::{
+    self, value_name, BlockState, Checkpoint, CheckpointSettings, CheckpointWriter,
+};
+use crate::color::ColorMode;
+use crate::distortion::Distortion;
+use crate::edge::EdgePolicy;
+use crate::enumerate::{Counters, EnumeratingApproximator};
+use crate::error::{Error, Result};
+use crate::hash::HashAlgorithm;
+use crate::mask::{self, FrozenBlocks, TextureMask, UnchangedApproximator};
+use crate::metric::ErrorMetric;
+use crate::nonce::NonceApproximator;
+use crate::preprocess::TargetPreprocessing;
+use crate::progress::{Progress, ProgressCallback, ProgressTracker};
+use crate::refine::{RankedBlock, Refinement};
+use crate::sidecar::BlockKey;
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImageView, Luma};
+use ndarray::{Array2, ArrayView2};
+use nshare::RefNdarray2;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Strategies to search for a good distortion of a block
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchStrategy {
+    /// Independent random distortions, the best one is kept
+    #[default]
+    Random,
+    /// Simulated annealing that changes the distortion of one pixel at a time
+    Anneal,
+    /// Enumerates the distortions like a counter so no distortion is tried twice
+    Enumerate,
+}
+
+/// What is changed to alter the hash of a block
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Perturbation {
+    /// The pixels of the source block are distorted
+    #[default]
+    Pixels,
+    /// The source is left untouched, a nonce appended to the pixels of every
+    /// block is searched instead
+    Nonce,
+}
+
+/// Outcome of a single block, passed to the block callback
+#[derive(Clone, Debug)]
+pub struct BlockResult {
+    /// Colour channel of the block, always 0 for grayscale images
+    pub channel: usize,
+    /// Pixel position of the top left corner of the block
+    pub x: u32,
+    pub y: u32,
+    pub error: f32,
+    /// Number of distortions tried for the block, including previous runs.
+    /// For [`SearchStrategy::Enumerate`] this is where a later run continues.
+    pub counter: u64,
+    /// Value of the counter when the best distortion was found, 0 if it was
+    /// not found by a search
+    pub found_at: u64,
+    /// Number of hashes computed for the block in this run, 0 for blocks
+    /// restored from a checkpoint unless they are refined
+    pub hashes: u64,
+    /// Nonce of the block in [`Perturbation::Nonce`] mode
+    pub nonce: Option<u64>,
+}
+
+/// Result of a run
+pub struct Approximation {
+    /// The distorted source image
+    pub source: DynamicImage,
+    /// The hashes of the distorted source image
+    pub target: DynamicImage,
+    /// Sum of the errors of all blocks
+    pub error: f32,
+    /// Results of the individual blocks, plane by plane in block order
+    pub blocks: Vec<BlockResult>,
+    /// Wall clock time of the run
+    pub elapsed: Duration,
+}
+
+/// A plane of the source and target image that is approximated on its own
+struct Plane {
+    source: GrayscaleImage,
+    target: GrayscaleImage,
+}
+
+/// A block together with the state of its search, refinement passes
+/// continue where the previous pass stopped
+struct BlockSearch {
+    channel: usize,
+    block: Block,
+    rng: BlockRng,
+    /// Counter at the start of the run
+    initial_counter: u64,
+    counter: u64,
+    found_at: u64,
+    best: BlockCandidate,
+}
+
+impl BlockSearch {
+    /// Restores a block that was finished in a previous run. The hashes of
+    /// that run are not counted as work of this run.
+    fn restore(seed: u64, channel: usize, block: Block, state: &BlockState) -> Self {
+        let mut rng = block_rng(seed, channel, block.x, block.y);
+        rng.set_word_pos(state.word_pos);
+        let shape = (block.height as usize, block.width as usize);
+        BlockSearch {
+            channel,
+            block,
+            rng,
+            initial_counter: state.counter,
+            counter: state.counter,
+            found_at: state.found_at,
+            best: BlockCandidate {
+                error: state.error,
+                // Only used to derive `found_at`, which is restored directly
+                iteration: 0,
+                source: Array2::from_shape_vec(shape, state.source.clone()).unwrap(),
+                target: Array2::from_shape_vec(shape, state.target.clone()).unwrap(),
+                nonce: state.nonce,
+            },
+        }
+    }
+
+    fn key(&self) -> BlockKey {
+        (self.channel, self.block.x, self.block.y)
+    }
+
+    fn state(&self) -> BlockState {
+        BlockState {
+            error: self.best.error,
+            counter: self.counter,
+            found_at: self.found_at,
+            word_pos: self.rng.get_word_pos(),
+            nonce: self.best.nonce,
+            source: self.best.source.iter().copied().collect(),
+            target: self.best.target.iter().copied().collect(),
+        }
+    }
+
+    fn result(&self) -> BlockResult {
+        BlockResult {
+            channel: self.channel,
+            x: self.block.x,
+            y: self.block.y,
+            error: self.best.error,
+            counter: self.counter,
+            found_at: self.found_at,
+            hashes: self.counter - self.initial_counter,
+            nonce: self.best.nonce,
+        }
+    }
+}
+
+type BlockCallback = Box<dyn Fn(&BlockResult) + Send + Sync>;
+
+/// Configures a [`HashArt`] run
+pub struct HashArtBuilder {
+    source: DynamicImage,
+    target: DynamicImage,
+    color_mode: ColorMode,
+    hash: HashAlgorithm,
+    block_size: Option<BlockSize>,
+    edge_policy: EdgePolicy,
+    strategy: SearchStrategy,
+    temperature_schedule: TemperatureSchedule,
+    perturbation: Perturbation,
+    distortion: Distortion,
+    target_preprocessing: TargetPreprocessing,
+    mask: Option<DynamicImage>,
+    texture_mask: Option<TextureMask>,
+    frozen_blocks: FrozenBlocks,
+    metric: ErrorMetric,
+    iterations: u64,
+    time_budget: TimeBudget,
+    refinement: Option<Refinement>,
+    counters: Counters,
+    seed: Option<u64>,
+    checkpoint: Option<(PathBuf, Duration)>,
+    resume: Option<Checkpoint>,
+    threads: Option<usize>,
+    on_block: Option<BlockCallback>,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl HashArtBuilder {
+    /// Whether the images are processed in grayscale or per colour channel
+    pub fn color_mode(mut self, color_mode: ColorMode) -> Self {
+        self.color_mode = color_mode;
+        self
+    }
+
+    /// The hash algorithm, defaults to SHA-512
+    pub fn hash(mut self, hash: HashAlgorithm) -> Self {
+        self.hash = hash;
+        self
+    }
+
+    /// The block size, defaults to the block size matching the digest length
+    pub fn block_size(mut self, block_size: Option<BlockSize>) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// How pixels at the edge that do not fill a complete block are handled
+    pub fn edge_policy(mut self, edge_policy: EdgePolicy) -> Self {
+        self.edge_policy = edge_policy;
+        self
+    }
+
+    /// The strategy used to search for good distortions
+    pub fn strategy(mut self, strategy: SearchStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// The temperature schedule of the [`SearchStrategy::Anneal`] strategy
+    pub fn temperature_schedule(mut self, schedule: TemperatureSchedule) -> Self {
+        self.temperature_schedule = schedule;
+        self
+    }
+
+    /// Whether the pixels or a nonce is changed to alter the hash of a block
+    pub fn perturbation(mut self, perturbation: Perturbation) -> Self {
+        self.perturbation = perturbation;
+        self
+    }
+
+    /// The range of the pixel distortions, defaults to adding 0 or 1
+    pub fn distortion(mut self, distortion: Distortion) -> Self {
+        self.distortion = distortion;
+        self
+    }
+
+    /// Fits the target to the size of the source and adjusts its values
+    /// before it is approximated
+    pub fn target_preprocessing(mut self, preprocessing: TargetPreprocessing) -> Self {
+        self.target_preprocessing = preprocessing;
+        self
+    }
+
+    /// Grayscale image of the size of the source that scales the distortion
+    /// of every pixel, 0 leaves the pixel untouched and 255 allows the full
+    /// amplitude. The same mask is used for all colour channels.
+    pub fn mask(mut self, mask: DynamicImage) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Derives a distortion mask from the texture of the source, so flat
+    /// regions are distorted less than textured ones. Combined with
+    /// [`HashArtBuilder::mask`] a pixel may only be distorted as far as both
+    /// masks allow.
+    pub fn texture_mask(mut self, texture_mask: TextureMask) -> Self {
+        self.texture_mask = Some(texture_mask);
+        self
+    }
+
+    /// What is done with blocks the mask leaves no distortion, defaults to
+    /// searching a nonce
+    pub fn frozen_blocks(mut self, frozen_blocks: FrozenBlocks) -> Self {
+        self.frozen_blocks = frozen_blocks;
+        self
+    }
+
+    /// The metric used to compare the hash of a block with the target block
+    pub fn metric(mut self, metric: ErrorMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// The number of distortions tried for every block. With a time budget
+    /// this is the maximum number of distortions.
+    pub fn iterations(mut self, iterations: u64) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Wall clock time of the whole run. The time is distributed evenly
+    /// across the blocks, once it has run out the remaining blocks only try
+    /// a single distortion.
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget.total = Some(budget);
+        self
+    }
+
+    /// Time spent searching a single block
+    pub fn block_time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget.block = Some(budget);
+        self
+    }
+
+    /// Additional passes on the blocks with the highest error after the
+    /// first pass over all blocks
+    pub fn refinement(mut self, refinement: Refinement) -> Self {
+        self.refinement = Some(refinement);
+        self
+    }
+
+    /// Counters of a previous run, the search of every block continues from
+    /// its counter. The best candidate of the previous run is recomputed
+    /// from its counter value and kept unless a better one is found. Blocks
+    /// without a counter start at 0.
+    pub fn counters(mut self, counters: Counters) -> Self {
+        self.counters = counters;
+        self
+    }
+
+    /// Seed for the random distortions, a random seed is chosen if none is set
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Writes a checkpoint to `path` whenever `interval` has passed since the
+    /// last one. The final checkpoint is written by
+    /// [`HashArt::write_checkpoint`].
+    pub fn checkpoint(mut self, path: impl Into<PathBuf>, interval: Duration) -> Self {
+        self.checkpoint = Some((path.into(), interval));
+        self
+    }
+
+    /// Continues a previous run from its checkpoint. Finished blocks are
+    /// restored instead of searched again and the seed of the checkpoint
+    /// replaces the seed of the builder. Refinement passes start again from
+    /// the first pass.
+    pub fn resume(mut self, checkpoint: Checkpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    /// Number of threads used to process blocks, defaults to all CPU cores
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Called whenever a block is finished. Blocks are processed in
+    /// parallel, so the callback is called from several threads and in no
+    /// particular order. With refinement passes it is called again for every
+    /// refined block.
+    pub fn on_block(mut self, callback: impl Fn(&BlockResult) + Send + Sync + 'static) -> Self {
+        self.on_block = Some(Box::new(callback));
+        self
+    }
+
+    /// Called with the progress of the run whenever a block is finished,
+    /// like [`HashArtBuilder::on_block`] from several threads
+    pub fn on_progress(mut self, callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Box::new(callback));
+        self
+    }
+
+    pub fn build(mut self) -> Result<HashArt> {
+        self.target = self.target_preprocessing.apply(
+            &self.target,
+            self.source.dimensions(),
+            self.color_mode,
+        );
+        if self.target.dimensions() != self.source.dimensions() {
+            return Err(Error::DimensionMismatch(
+                self.source.dimensions(),
+                self.target.dimensions(),
+            ));
+        }
+        if let Some(mask) = self
+            .mask
+            .as_ref()
+            .filter(|mask| mask.dimensions() != self.source.dimensions())
+        {
+            return Err(Error::DimensionMismatch(
+                self.source.dimensions(),
+                mask.dimensions(),
+            ));
+        }
+        if self
+            .texture_mask
+            .is_some_and(|texture_mask| texture_mask.full_deviation <= 0.0)
+        {
+            return Err(Error::InvalidConfig(
+                "the full deviation of the texture mask must be positive".to_string(),
+            ));
+        }
+        if self.strategy == SearchStrategy::Anneal && !self.temperature_schedule.is_valid() {
+            return Err(Error::InvalidConfig(
+                "the start and end temperature of the anneal strategy must be positive".to_string(),
+            ));
+        }
+        if !(1..=self.distortion.max_amplitude()).contains(&self.distortion.amplitude) {
+            let range = self.distortion.range.to_possible_value().unwrap();
+            return Err(Error::InvalidConfig(format!(
+                "the amplitude of the {} distortion must be between 1 and {}",
+                range.get_name(),
+                self.distortion.max_amplitude()
+            )));
+        }
+        let (hasher, block_size) = self.hash.hasher_for(self.block_size)?;
+        let distortion = self.distortion;
+        let metric = self.metric.metric();
+        let approximator: Box<dyn BlockApproximator> = match (self.perturbation, self.strategy) {
+            (Perturbation::Pixels, SearchStrategy::Random) => {
+                Box::new(HashApproximator::new(hasher, metric, distortion))
+            }
+            (Perturbation::Pixels, SearchStrategy::Anneal) => Box::new(AnnealingApproximator::new(
+                hasher,
+                metric,
+                distortion,
+                self.temperature_schedule,
+            )),
+            (Perturbation::Pixels, SearchStrategy::Enumerate) => {
+                Box::new(EnumeratingApproximator::new(hasher, metric, distortion))
+            }
+            (Perturbation::Nonce, SearchStrategy::Random) => {
+                Box::new(NonceApproximator::new(hasher, metric, true))
+            }
+            (Perturbation::Nonce, SearchStrategy::Enumerate) => {
+                Box::new(NonceApproximator::new(hasher, metric, false))
+            }
+            (Perturbation::Nonce, SearchStrategy::Anneal) => {
+                return Err(Error::InvalidConfig(
+                    "the anneal strategy does not support nonces".to_string(),
+                ))
+            }
+        };
+        // Blocks the mask leaves no distortion are searched separately
+        let frozen: Option<Box<dyn BlockApproximator>> = match self.perturbation {
+            Perturbation::Pixels if self.mask.is_some() || self.texture_mask.is_some() => {
+                let (hasher, _) = self.hash.hasher_for(self.block_size)?;
+                let metric = self.metric.metric();
+                Some(match self.frozen_blocks {
+                    FrozenBlocks::Nonce => Box::new(NonceApproximator::new(
+                        hasher,
+                        metric,
+                        self.strategy != SearchStrategy::Enumerate,
+                    )),
+                    FrozenBlocks::Skip => Box::new(UnchangedApproximator::new(hasher, metric)),
+                })
+            }
+            _ => None,
+        };
+        let pool = self
+            .threads
+            .map(|threads| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .map_err(|e| Error::InvalidConfig(format!("cannot create thread pool: {e}")))
+            })
+            .transpose()?;
+        let (width, height) = self.source.dimensions();
+        let mut mask = match &self.mask {
+            Some(mask) => mask.to_luma8(),
+            None => GrayscaleImage::from_pixel(width, height, Luma([u8::MAX])),
+        };
+        if let Some(texture_mask) = self.texture_mask {
+            mask = mask::combine(&mask, &texture_mask.compute(&self.source.to_luma8()));
+        }
+        let mask = self.edge_policy.prepare_mask(&mask, block_size);
+        let sources = self.color_mode.split(&self.source);
+        let targets = self.color_mode.split(&self.target);
+        let planes = sources
+            .iter()
+            .zip(&targets)
+            .map(|(source, target)| {
+                let mut source = self.edge_policy.prepare(source, block_size);
+                if self.perturbation == Perturbation::Pixels {
+                    source = distortion.prepare(&source, &mask);
+                }
+                Plane {
+                    source,
+                    target: self.edge_policy.prepare(target, block_size),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let seed = match &self.resume {
+            Some(checkpoint) => checkpoint.seed,
+            None => self.seed.unwrap_or_else(ndarray_rand::rand::random),
+        };
+        let settings = CheckpointSettings {
+            hash: self.hash,
+            block_size,
+            color_mode: self.color_mode,
+            edge_policy: self.edge_policy,
+            strategy: self.strategy,
+            temperature_schedule: self.temperature_schedule,
+            perturbation: self.perturbation,
+            distortion,
+            frozen_blocks: self.frozen_blocks,
+            metric: self.metric,
+            target_preprocessing: self.target_preprocessing,
+            source: checkpoint::digest(planes.iter().map(|plane| &plane.source)),
+            target: checkpoint::digest(planes.iter().map(|plane| &plane.target)),
+            mask: checkpoint::digest([&mask]),
+        };
+        let resume = match self.resume {
+            Some(checkpoint) => {
+                let blocks = self
+                    .edge_policy
+                    .blocks(planes[0].source.dimensions(), block_size);
+                check_checkpoint(&checkpoint, &settings, &blocks, planes.len())?;
+                checkpoint.blocks
+            }
+            None => HashMap::new(),
+        };
+        let checkpoint = self
+            .checkpoint
+            .map(|(path, interval)| {
+                let mut checkpoint = Checkpoint::new(seed, settings);
+                checkpoint.blocks = resume.clone();
+                let writer = CheckpointWriter::new(path, interval, checkpoint);
+                writer.write()?;
+                Ok(writer)
+            })
+            .transpose()?;
+
+        let progress = self.on_progress.map(|callback| {
+            let blocks = self
+                .edge_policy
+                .blocks(planes[0].source.dimensions(), block_size);
+            ProgressTracker::new(callback, blocks.len() * planes.len())
+        });
+
+        Ok(HashArt {
+            planes,
+            color_mode: self.color_mode,
+            block_size,
+            edge_policy: self.edge_policy,
+            mask,
+            distortion,
+            approximator,
+            frozen,
+            iterations: self.iterations,
+            time_budget: self.time_budget,
+            refinement: self.refinement,
+            counters: self.counters,
+            seed,
+            resume,
+            checkpoint,
+            pool,
+            on_block: self.on_block,
+            progress,
+        })
+    }
+}
+
+/// Approximates a target image with the hashes of the blocks of a source image
+pub struct HashArt {
+    planes: Vec<Plane>,
+    color_mode: ColorMode,
+    block_size: BlockSize,
+    edge_policy: EdgePolicy,
+    /// Distortion mask after the edge policy has been applied
+    mask: GrayscaleImage,
+    distortion: Distortion,
+    approximator: Box<dyn BlockApproximator>,
+    /// Approximator of the blocks the mask leaves no distortion
+    frozen: Option<Box<dyn BlockApproximator>>,
+    iterations: u64,
+    time_budget: TimeBudget,
+    refinement: Option<Refinement>,
+    counters: Counters,
+    seed: u64,
+    resume: HashMap<BlockKey, BlockState>,
+    checkpoint: Option<CheckpointWriter>,
+    pool: Option<ThreadPool>,
+    on_block: Option<BlockCallback>,
+    progress: Option<ProgressTracker>,
+}
+
+impl HashArt {
+    pub fn builder(source: DynamicImage, target: DynamicImage) -> HashArtBuilder {
+        HashArtBuilder {
+            source,
+            target,
+            color_mode: ColorMode::default(),
+            hash: HashAlgorithm::Sha512,
+            block_size: None,
+            edge_policy: EdgePolicy::default(),
+            strategy: SearchStrategy::default(),
+            temperature_schedule: TemperatureSchedule::default(),
+            perturbation: Perturbation::default(),
+            distortion: Distortion::default(),
+            target_preprocessing: TargetPreprocessing::default(),
+            mask: None,
+            texture_mask: None,
+            frozen_blocks: FrozenBlocks::default(),
+            metric: ErrorMetric::default(),
+            iterations: 100,
+            time_budget: TimeBudget::default(),
+            refinement: None,
+            counters: Counters::new(),
+            seed: None,
+            checkpoint: None,
+            resume: None,
+            threads: None,
+            on_block: None,
+            on_progress: None,
+        }
+    }
+
+    pub fn block_size(&self) -> BlockSize {
+        self.block_size
+    }
+
+    pub fn edge_policy(&self) -> EdgePolicy {
+        self.edge_policy
+    }
+
+    pub fn color_mode(&self) -> ColorMode {
+        self.color_mode
+    }
+
+    /// The target after preprocessing and the edge policy
+    pub fn target(&self) -> DynamicImage {
+        self.color_mode.merge(
+            self.planes
+                .iter()
+                .map(|plane| plane.target.clone())
+                .collect(),
+        )
+    }
+
+    /// The distortion mask after the edge policy has been applied, white
+    /// everywhere if neither a mask nor a texture mask is configured
+    pub fn mask(&self) -> &GrayscaleImage {
+        &self.mask
+    }
+
+    /// Dimensions of the images after the edge policy has been applied
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.planes[0].source.dimensions()
+    }
+
+    /// The seed of the run, the same seed reproduces the same result
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Number of blocks restored from a checkpoint
+    pub fn resumed_blocks(&self) -> usize {
+        self.resume.len()
+    }
+
+    /// Writes the checkpoint with the state of all blocks finished so far,
+    /// does nothing if no checkpoint is configured
+    pub fn write_checkpoint(&self) -> Result<()> {
+        match &self.checkpoint {
+            Some(checkpoint) => checkpoint.write(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn run(&self) -> Approximation {
+        let start = Instant::now();
+        let mut approximation = match &self.pool {
+            Some(pool) => pool.install(|| self.approximate_image()),
+            None => self.approximate_image(),
+        };
+        approximation.elapsed = start.elapsed();
+        approximation
+    }
+
+    fn approximate_image(&self) -> Approximation {
+        if let Some(progress) = &self.progress {
+            progress.reset();
+        }
+        let deadline = self.time_budget.total.map(|total| Instant::now() + total);
+        let passes = 1 + self.refinement.map_or(0, |refinement| refinement.passes);
+        let blocks = self.edge_policy.blocks(self.dimensions(), self.block_size);
+        let block_time = self.block_time(deadline, passes, blocks.len() * self.planes.len());
+        let mut searches: Vec<_> = (0..self.planes.len())
+            .flat_map(|channel| blocks.iter().map(move |block| (channel, *block)))
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(channel, block)| {
+                let key = (channel, block.x, block.y);
+                if let Some(state) = self.resume.get(&key) {
+                    let search = BlockSearch::restore(self.seed, channel, block, state);
+                    self.report(&search);
+                    return search;
+                }
+                let stored = self.counters.get(&key).copied().unwrap_or_default();
+                let initial_counter = stored.counter;
+                let mut rng = block_rng(self.seed, channel, block.x, block.y);
+                let mut counter = initial_counter;
+                let mut best =
+                    self.search_block(channel, block, deadline, block_time, &mut rng, &mut counter);
+                let mut found_at = best.found_at(initial_counter);
+                // Keep the best candidate of the run the counters come from
+                // unless this run found a better one
+                if let Some(previous) = self
+                    .replay_block(channel, block, stored.found_at)
+                    .filter(|previous| previous.error <= best.error)
+                {
+                    best = previous;
+                    found_at = stored.found_at;
+                }
+                let search = BlockSearch {
+                    channel,
+                    block,
+                    rng,
+                    initial_counter,
+                    counter,
+                    found_at,
+                    best,
+                };
+                self.report(&search);
+                search
+            })
+            .collect();
+
+        if let Some(refinement) = self.refinement {
+            self.refine(&mut searches, refinement, deadline);
+        }
+        self.assemble(searches)
+    }
+
+    /// Runs the refinement passes. The blocks are kept in a priority queue
+    /// ordered by their error, every pass takes the selected blocks from the
+    /// queue, searches them again and puts them back with their new error.
+    fn refine(
+        &self,
+        searches: &mut [BlockSearch],
+        refinement: Refinement,
+        deadline: Option<Instant>,
+    ) {
+        let mut queue: BinaryHeap<_> = searches
+            .iter()
+            .enumerate()
+            .map(|(index, search)| RankedBlock {
+                error: search.best.error,
+                index,
+            })
+            .collect();
+        for pass in 0..refinement.passes {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                break;
+            }
+            let selected = refinement.selection.take(&mut queue);
+            if selected.is_empty() {
+                break;
+            }
+            let block_time = self.block_time(deadline, refinement.passes - pass, selected.len());
+            let mut refine = vec![false; searches.len()];
+            for block in &selected {
+                refine[block.index] = true;
+            }
+            searches
+                .par_iter_mut()
+                .zip(refine)
+                .filter(|(_, refine)| *refine)
+                .for_each(|(search, _)| {
+                    let counter = search.counter;
+                    let candidate = self.search_block(
+                        search.channel,
+                        search.block,
+                        deadline,
+                        block_time,
+                        &mut search.rng,
+                        &mut search.counter,
+                    );
+                    if candidate.error < search.best.error {
+                        search.found_at = candidate.found_at(counter);
+                        search.best = candidate;
+                    }
+                    self.report(search);
+                });
+            queue.extend(selected.iter().map(|block| RankedBlock {
+                error: searches[block.index].best.error,
+                index: block.index,
+            }));
+        }
+    }
+
+    /// Time a block of a pass may take, the remaining total time is split
+    /// evenly between the remaining passes and the blocks of a pass
+    fn block_time(
+        &self,
+        deadline: Option<Instant>,
+        passes: usize,
+        blocks: usize,
+    ) -> Option<Duration> {
+        TimeBudget {
+            total: deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()) / passes as u32),
+            block: self.time_budget.block,
+        }
+        .per_block(blocks, rayon::current_num_threads())
+    }
+
+    /// Searches a single block, continuing its random number generator and
+    /// counter
+    fn search_block(
+        &self,
+        channel: usize,
+        block: Block,
+        deadline: Option<Instant>,
+        block_time: Option<Duration>,
+        rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let [input_block, target_block, mask_block] = self.block_images(channel, block);
+        let mask_block = mask_block.ref_ndarray2();
+        // The block stops at its own deadline or when the run is out of time
+        let block_deadline = block_time.map(|time| Instant::now() + time);
+        let budget = Budget::new(
+            self.iterations,
+            block_deadline.into_iter().chain(deadline).min(),
+        );
+        self.approximator_for(&mask_block).approximate(
+            &input_block.ref_ndarray2(),
+            &target_block.ref_ndarray2(),
+            &mask_block,
+            &budget,
+            rng,
+            counter,
+        )
+    }
+
+    /// Recomputes the best candidate of a previous run from the value of the
+    /// counter at which it was found
+    fn replay_block(&self, channel: usize, block: Block, found_at: u64) -> Option<BlockCandidate> {
+        // The candidate was tried before the counter was advanced
+        let counter = found_at.checked_sub(1)?;
+        let [input_block, target_block, mask_block] = self.block_images(channel, block);
+        let mask_block = mask_block.ref_ndarray2();
+        self.approximator_for(&mask_block).replay(
+            &input_block.ref_ndarray2(),
+            &target_block.ref_ndarray2(),
+            &mask_block,
+            counter,
+        )
+    }
+
+    /// The source, target and distortion mask of a block
+    fn block_images(&self, channel: usize, block: Block) -> [GrayscaleImage; 3] {
+        let plane = &self.planes[channel];
+        let Block {
+            x,
+            y,
+            width,
+            height,
+        } = block;
+        [&plane.source, &plane.target, &self.mask]
+            .map(|image| image::imageops::crop_imm(image, x, y, width, height).to_image())
+    }
+
+    /// The approximator of a block with the given distortion mask
+    fn approximator_for(&self, mask_block: &ArrayView2<u8>) -> &dyn BlockApproximator {
+        match &self.frozen {
+            Some(frozen) if self.distortion.is_frozen(mask_block) => frozen.as_ref(),
+            _ => self.approximator.as_ref(),
+        }
+    }
+
+    fn report(&self, search: &BlockSearch) {
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.update(search.key(), search.state());
+        }
+        let result = search.result();
+        if let Some(progress) = &self.progress {
+            progress.update(search.key(), result.error, result.hashes);
+        }
+        if let Some(on_block) = &self.on_block {
+            on_block(&result);
+        }
+    }
+
+    /// Assembles the images from the best candidates of the blocks. Blocks
+    /// are processed in parallel but assembled in block order, so for a
+    /// given seed the output does not depend on the number of threads.
+    fn assemble(&self, searches: Vec<BlockSearch>) -> Approximation {
+        let mut sources: Vec<_> = self
+            .planes
+            .iter()
+            .map(|plane| plane.source.clone())
+            .collect();
+        let mut targets = sources.clone();
+        let mut total_error = 0.0;
+        let mut blocks = Vec::with_capacity(searches.len());
+        for search in searches {
+            let (source, target) = (&mut sources[search.channel], &mut targets[search.channel]);
+            let block = search.block;
+            for n in 0..block.height {
+                for m in 0..block.width {
+                    let (x, y) = (block.x + m, block.y + n);
+                    let index = (n as usize, m as usize);
+                    source.put_pixel(x, y, Luma([search.best.source[index]]));
+                    target.put_pixel(x, y, Luma([search.best.target[index]]));
+                }
+            }
+            total_error += search.best.error;
+            blocks.push(search.result());
+        }
+        Approximation {
+            source: self.color_mode.merge(sources),
+            target: self.color_mode.merge(targets),
+            error: total_error,
+            blocks,
+            elapsed: Duration::ZERO,
+        }
+    }
+}
+
+/// Checks that a checkpoint was written with the same settings and block
+/// layout
+fn check_checkpoint(
+    checkpoint: &Checkpoint,
+    settings: &CheckpointSettings,
+    blocks: &[Block],
+    channels: usize,
+) -> Result<()> {
+    let written = &checkpoint.settings;
+    let preprocessing = |preprocessing: &TargetPreprocessing| {
+        format!(
+            "fit {}, stretch contrast {}, equalize {}",
+            value_name(&preprocessing.fit),
+            preprocessing.stretch_contrast,
+            preprocessing.equalize
+        )
+    };
+    let values = [
+        (
+            "the hash",
+            value_name(&written.hash),
+            value_name(&settings.hash),
+        ),
+        (
+            "block size",
+            written.block_size.to_string(),
+            settings.block_size.to_string(),
+        ),
+        (
+            "the colour mode",
+            value_name(&written.color_mode),
+            value_name(&settings.color_mode),
+        ),
+        (
+            "the edge policy",
+            value_name(&written.edge_policy),
+            value_name(&settings.edge_policy),
+        ),
+        (
+            "the strategy",
+            value_name(&written.strategy),
+            value_name(&settings.strategy),
+        ),
+        (
+            "the perturbation",
+            value_name(&written.perturbation),
+            value_name(&settings.perturbation),
+        ),
+        (
+            "the temperature schedule",
+            written.temperature_schedule.to_string(),
+            settings.temperature_schedule.to_string(),
+        ),
+        (
+            "the distortion",
+            written.distortion.to_string(),
+            settings.distortion.to_string(),
+        ),
+        (
+            "frozen blocks",
+            value_name(&written.frozen_blocks),
+            value_name(&settings.frozen_blocks),
+        ),
+        (
+            "the target preprocessing",
+            preprocessing(&written.target_preprocessing),
+            preprocessing(&settings.target_preprocessing),
+        ),
+        (
+            "the metric",
+            value_name(&written.metric),
+            value_name(&settings.metric),
+        ),
+    ];
+    if let Some((setting, written, expected)) = values
+        .into_iter()
+        .find(|(_, written, expected)| written != expected)
+    {
+        return Err(Error::InvalidConfig(format!(
+            "the checkpoint was written for {setting} {written} instead of {expected}"
+        )));
+    }
+    let images = [
+        // The source is prepared with the mask, so the mask is checked first
+        ("distortion mask", written.mask, settings.mask),
+        ("source", written.source, settings.source),
+        ("target", written.target, settings.target),
+    ];
+    if let Some((image, _, _)) = images
+        .into_iter()
+        .find(|(_, written, expected)| written != expected)
+    {
+        return Err(Error::InvalidConfig(format!(
+            "the checkpoint was written for a different {image}"
+        )));
+    }
+    let pixels: HashMap<_, _> = blocks
+        .iter()
+        .map(|block| ((block.x, block.y), (block.width * block.height) as usize))
+        .collect();
+    for (&(channel, x, y), state) in &checkpoint.blocks {
+        let matches = channel < channels
+            && pixels.get(&(x, y)).is_some_and(|&pixels| {
+                state.source.len() == pixels && state.target.len() == pixels
+            });
+        if !matches {
+            return Err(Error::InvalidConfig(format!(
+                "block at ({x}, {y}) of channel {channel} in the checkpoint does not match the images"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::refine::Selection;
+    use image::GrayImage;
+
+    fn gradient(width: u32, height: u32, offset: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([((x * 13 + y * 7 + offset) % 256) as u8])
+        }))
+    }
+
+    fn run(threads: usize) -> Approximation {
+        HashArt::builder(gradient(19, 9, 0), gradient(19, 9, 100))
+            .hash(HashAlgorithm::Sha256)
+            .iterations(50)
+            .refinement(Refinement {
+                passes: 2,
+                selection: Selection::Worst(3),
+            })
+            .seed(7)
+            .threads(threads)
+            .build()
+            .unwrap()
+            .run()
+    }
+
+    #[test]
+    fn output_does_not_depend_on_threads() {
+        let single = run(1);
+        let parallel = run(4);
+        assert_eq!(single.source, parallel.source);
+        assert_eq!(single.target, parallel.target);
+        assert_eq!(single.error, parallel.error);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::approximator::{BlockApproximator, BlockCandidate, BlockRng};
+use crate::budget::Budget;
+use crate::distortion::Distortion;
+use crate::engine::BlockResult;
+use crate::error::Result;
+use crate::hash::BlockHasher;
+use crate::metric::BlockMetric;
+use crate::sidecar::{self, BlockKey};
+use ndarray::prelude::*;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Counter of a block and the value of the counter when its best
+/// distortion was found, see [`crate::BlockResult`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockCounter {
+    pub counter: u64,
+    /// 0 if no distortion was found by the counter
+    pub found_at: u64,
+}
+
+/// Counters of the blocks
+pub type Counters = HashMap<BlockKey, BlockCounter>;
+
+/// Walks the distortion space of a block like a counter, so every iteration
+/// tests a distinct distortion. The counter is a mixed-radix number whose
+/// digits are the distortion levels of the pixels in row-major order, the
+/// base of a pixel is the number of distinct values its
+/// [`Distortion::masked`] distortion reaches, see
+/// [`Distortion::distinct_levels`]. Pixels frozen by the mask have no digit.
+pub struct EnumeratingApproximator {
+    hasher: Box<dyn BlockHasher>,
+    metric: Box<dyn BlockMetric>,
+    distortion: Distortion,
+}
+
+impl EnumeratingApproximator {
+    pub fn new(
+        hasher: Box<dyn BlockHasher>,
+        metric: Box<dyn BlockMetric>,
+        distortion: Distortion,
+    ) -> Self {
+        EnumeratingApproximator {
+            hasher,
+            metric,
+            distortion,
+        }
+    }
+
+    /// Distorts the block by the digits of `counter`, `None` if the counter
+    /// is past the distortion space of the block
+    pub fn decode(
+        &self,
+        input: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        counter: u64,
+    ) -> Option<Array2<u8>> {
+        let mut source = input.to_owned();
+        let mut digits = counter;
+        for (pixel, &mask) in source.iter_mut().zip(mask) {
+            let distortion = self.distortion.masked(mask);
+            let levels = distortion.distinct_levels(*pixel);
+            let base = u64::from(levels.end - levels.start);
+            if base > 1 {
+                let level = levels.start + (digits % base) as u8;
+                *pixel = distortion.apply(*pixel, level, u8::MAX);
+                digits /= base;
+            }
+        }
+        (digits == 0).then_some(source)
+    }
+
+    /// Hashes a distorted source block that was not found by the search
+    fn candidate(&self, source: Array2<u8>, target: &ArrayView2<u8>) -> BlockCandidate {
+        let mut output = vec![0; source.len()];
+        self.hasher.hash(source.as_slice().unwrap(), &mut output);
+        let hashed = Array::from_shape_vec(source.dim(), output).unwrap();
+        BlockCandidate {
+            error: self.metric.error(target, &hashed.view()),
+            iteration: 0,
+            source,
+            target: hashed,
+            nonce: None,
+        }
+    }
+}
+
+impl BlockApproximator for EnumeratingApproximator {
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        budget: &Budget,
+        _rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let shape = input.dim();
+        let mut best: Option<BlockCandidate> = None;
+        let mut output = vec![0; shape.0 * shape.1];
+
+        let mut iteration = 0;
+        while !budget.exhausted(iteration) {
+            // Stops once all distortions of the block have been tested
+            let Some(current_source) = self.decode(input, mask, *counter) else {
+                break;
+            };
+            iteration += 1;
+            *counter += 1;
+
+            self.hasher
+                .hash(current_source.as_slice().unwrap(), &mut output);
+            let current_target = ArrayView::from_shape(shape, &output).unwrap();
+            let total_error = self.metric.error(target, &current_target);
+
+            if best.as_ref().is_none_or(|best| best.error > total_error) {
+                best = Some(BlockCandidate {
+                    error: total_error,
+                    iteration,
+                    source: current_source,
+                    target: current_target.to_owned(),
+                    nonce: None,
+                });
+            }
+        }
+        // A block whose distortion space was already exhausted keeps its
+        // source, it is hashed once without advancing the counter
+        best.unwrap_or_else(|| self.candidate(input.to_owned(), target))
+    }
+
+    fn replay(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        mask: &ArrayView2<u8>,
+        counter: u64,
+    ) -> Option<BlockCandidate> {
+        let source = self.decode(input, mask, counter)?;
+        Some(self.candidate(source, target))
+    }
+}
+
+/// Reads counters written by [`write_counters`]. Files without the
+/// `found` column restore no best distortion.
+pub fn read_counters(path: &Path) -> Result<Counters> {
+    let counters = sidecar::read(path)?;
+    Ok(counters
+        .into_iter()
+        .map(|(key, values)| {
+            let counter = BlockCounter {
+                counter: values[0],
+                found_at: values.get(1).copied().unwrap_or(0),
+            };
+            (key, counter)
+        })
+        .collect())
+}
+
+/// Writes the counter of every block together with the value at which its
+/// best distortion was found, so a later run can restore that distortion
+pub fn write_counters(path: &Path, blocks: &[BlockResult]) -> Result<()> {
+    let counters = blocks.iter().map(|block| {
+        (
+            (block.channel, block.x, block.y),
+            [block.counter, block.found_at],
+        )
+    });
+    sidecar::write(path, ["counter", "found"], counters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distortion::DistortionRange;
+    use crate::hash::HashAlgorithm;
+    use crate::metric::ErrorMetric;
+    use std::collections::HashSet;
+
+    fn approximator(range: DistortionRange, amplitude: u8) -> EnumeratingApproximator {
+        EnumeratingApproximator::new(
+            HashAlgorithm::Shake256.hasher(),
+            ErrorMetric::default().metric(),
+            Distortion { range, amplitude },
+        )
+    }
+
+    #[test]
+    fn decode_digits() {
+        let approximator = approximator(DistortionRange::Additive, 2);
+        let input = array![[10, 20], [30, 40]];
+        let mask = Array2::from_elem((2, 2), u8::MAX);
+        // 5 = 2 + 1 * 3 in base 3, the first pixel is the lowest digit
+        let source = approximator.decode(&input.view(), &mask.view(), 5);
+        assert_eq!(source, Some(array![[12, 21], [30, 40]]));
+        let last = approximator.decode(&input.view(), &mask.view(), 80);
+        assert_eq!(last, Some(array![[12, 22], [32, 42]]));
+        assert_eq!(approximator.decode(&input.view(), &mask.view(), 81), None);
+    }
+
+    #[test]
+    fn decode_skips_frozen_pixels() {
+        let approximator = approximator(DistortionRange::Signed, 2);
+        let input = array![[10, 20], [30, 40]];
+        // Full, frozen, half (amplitude 1) and frozen
+        let mask = array![[255, 0], [128, 0]];
+        let sources: Vec<_> = (0..)
+            .map_while(|counter| approximator.decode(&input.view(), &mask.view(), counter))
+            .collect();
+        assert_eq!(sources.len(), 5 * 3);
+        assert_eq!(sources.iter().collect::<HashSet<_>>().len(), sources.len());
+        assert!(sources
+            .iter()
+            .all(|source| source[(0, 1)] == 20 && source[(1, 1)] == 40));
+    }
+
+    #[test]
+    fn decode_masked_lsb_patterns_once() {
+        let approximator = approximator(DistortionRange::Lsb, 3);
+        let input = array![[0b1010_1010, 0b0101_0101]];
+        // Amplitude 3 and 1, so 8 * 2 patterns
+        let mask = array![[255, 85]];
+        let sources: Vec<_> = (0..)
+            .map_while(|counter| approximator.decode(&input.view(), &mask.view(), counter))
+            .collect();
+        assert_eq!(sources.len(), 16);
+        assert_eq!(sources.iter().collect::<HashSet<_>>().len(), sources.len());
+    }
+
+    #[test]
+    fn decode_clamped_values_once() {
+        let approximator = approximator(DistortionRange::Signed, 2);
+        // 3, 3, 4 and 5 distinct values
+        let input = array![[0, 255], [1, 128]];
+        let mask = Array2::from_elem((2, 2), u8::MAX);
+        let sources: Vec<_> = (0..)
+            .map_while(|counter| approximator.decode(&input.view(), &mask.view(), counter))
+            .collect();
+        assert_eq!(sources.len(), 3 * 3 * 4 * 5);
+        assert_eq!(sources.iter().collect::<HashSet<_>>().len(), sources.len());
+    }
+
+    #[test]
+    fn exhausted_block_keeps_its_source() {
+        let approximator = approximator(DistortionRange::Additive, 1);
+        let input = array![[10, 20], [30, 40]];
+        let target = Array2::zeros((2, 2));
+        let mask = Array2::from_elem((2, 2), u8::MAX);
+        let mut counter = 16;
+        let candidate = approximator.approximate(
+            &input.view(),
+            &target.view(),
+            &mask.view(),
+            &Budget::new(10, None),
+            &mut crate::approximator::block_rng(0, 0, 0, 0),
+            &mut counter,
+        );
+        assert_eq!(counter, 16);
+        assert_eq!(candidate.source, input);
+        assert!(candidate.error.is_finite());
+    }
+}
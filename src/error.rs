@@ -0,0 +1,93 @@
+use image::ImageError;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors of the library and the command line tool
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io { path: PathBuf, error: io::Error },
+    /// An image file could not be decoded
+    Decode { path: PathBuf, error: ImageError },
+    /// An image could not be encoded, e.g. because of an unknown extension
+    Encode { path: PathBuf, error: ImageError },
+    /// Two images that must have the same size differ in size
+    DimensionMismatch((u32, u32), (u32, u32)),
+    /// The options do not fit together or do not fit the images
+    InvalidConfig(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn io(path: &Path, error: io::Error) -> Self {
+        Error::Io {
+            path: path.to_owned(),
+            error,
+        }
+    }
+
+    /// Exit code of the command line tool. 1 is used for failed
+    /// verifications and 2 by the argument parser for invalid arguments.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::InvalidConfig(_) => 2,
+            Error::Io { .. } => 3,
+            Error::Decode { .. } => 4,
+            Error::Encode { .. } => 5,
+            Error::DimensionMismatch(..) => 6,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, error } => write!(f, "{}: {error}", path.display()),
+            Error::Decode { path, error } => {
+                write!(f, "cannot decode image {}: {error}", path.display())
+            }
+            Error::Encode { path, error } => {
+                write!(f, "cannot encode image {}: {error}", path.display())
+            }
+            Error::DimensionMismatch((width, height), (other_width, other_height)) => write!(
+                f,
+                "images must have the same size, but are {width}x{height} and {other_width}x{other_height}"
+            ),
+            Error::InvalidConfig(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { error, .. } => Some(error),
+            Error::Decode { error, .. } | Error::Encode { error, .. } => Some(error),
+            Error::DimensionMismatch(..) | Error::InvalidConfig(_) => None,
+        }
+    }
+}
+
+/// Opens an image file
+pub fn open_image(path: &Path) -> Result<image::DynamicImage> {
+    image::open(path).map_err(|error| match error {
+        ImageError::IoError(error) => Error::io(path, error),
+        error => Error::Decode {
+            path: path.to_owned(),
+            error,
+        },
+    })
+}
+
+/// Saves an image, the format is chosen by the extension of the path
+pub fn save_image(image: &image::DynamicImage, path: &Path) -> Result<()> {
+    image.save(path).map_err(|error| match error {
+        ImageError::IoError(error) => Error::io(path, error),
+        error => Error::Encode {
+            path: path.to_owned(),
+            error,
+        },
+    })
+}
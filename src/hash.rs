@@ -0,0 +1,165 @@
+use crate::block::BlockSize;
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use sha2::digest::{ExtendableOutput, XofReader};
+use sha2::Digest;
+use std::marker::PhantomData;
+
+/// Hash algorithms that can be used to turn a source block into a target block
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+    #[value(name = "sha3-512")]
+    Sha3_512,
+    Blake2b,
+    Blake3,
+    Md5,
+    /// Extendable output, supports arbitrary block sizes
+    Shake256,
+    /// Extendable output, supports arbitrary block sizes
+    #[value(name = "blake3-xof")]
+    Blake3Xof,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(self) -> Box<dyn BlockHasher> {
+        match self {
+            HashAlgorithm::Sha256 => Box::new(DigestHasher::<sha2::Sha256>::new()),
+            HashAlgorithm::Sha384 => Box::new(DigestHasher::<sha2::Sha384>::new()),
+            HashAlgorithm::Sha512 => Box::new(DigestHasher::<sha2::Sha512>::new()),
+            HashAlgorithm::Sha3_512 => Box::new(DigestHasher::<sha3::Sha3_512>::new()),
+            HashAlgorithm::Blake2b => Box::new(DigestHasher::<blake2::Blake2b512>::new()),
+            HashAlgorithm::Blake3 => Box::new(Blake3Hasher),
+            HashAlgorithm::Md5 => Box::new(DigestHasher::<md5::Md5>::new()),
+            HashAlgorithm::Shake256 => Box::new(XofHasher::<sha3::Shake256>::new()),
+            HashAlgorithm::Blake3Xof => Box::new(Blake3XofHasher),
+        }
+    }
+
+    /// The hasher together with the block size it is applied to. Without an
+    /// explicit block size the digest length determines the block size.
+    pub fn hasher_for(
+        self,
+        block_size: Option<BlockSize>,
+    ) -> Result<(Box<dyn BlockHasher>, BlockSize)> {
+        let hasher = self.hasher();
+        let digest_len = hasher.digest_len();
+        let block_size = block_size
+            .or(digest_len.map(BlockSize::for_digest_len))
+            .unwrap_or_default();
+        if !hasher.supports_block_size(block_size) {
+            return Err(Error::InvalidConfig(format!(
+                "{self:?} produces {} bytes which does not fit a {block_size} block, use an extendable-output hash for other block sizes",
+                digest_len.unwrap_or_default()
+            )));
+        }
+        Ok((hasher, block_size))
+    }
+}
+
+/// Hashes the pixels of a block into the pixels of a target block
+pub trait BlockHasher: Send + Sync {
+    /// Number of bytes the hash produces, `None` for extendable-output functions
+    fn digest_len(&self) -> Option<usize>;
+
+    /// Whether the hash can produce one byte for every pixel of the block
+    fn supports_block_size(&self, size: BlockSize) -> bool {
+        self.digest_len().is_none_or(|len| len == size.pixels())
+    }
+
+    /// Hashes `data` and fills `output` with the result. For fixed-size
+    /// digests `output` must not be longer than `digest_len`, shorter outputs
+    /// get a truncated digest.
+    fn hash(&self, data: &[u8], output: &mut [u8]);
+}
+
+/// Hasher for any fixed-size `Digest`
+pub struct DigestHasher<D> {
+    digest: PhantomData<fn() -> D>,
+}
+
+impl<D: Digest> DigestHasher<D> {
+    pub fn new() -> Self {
+        DigestHasher {
+            digest: PhantomData,
+        }
+    }
+}
+
+impl<D: Digest> Default for DigestHasher<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<D: Digest> BlockHasher for DigestHasher<D> {
+    fn digest_len(&self) -> Option<usize> {
+        Some(<D as Digest>::output_size())
+    }
+
+    fn hash(&self, data: &[u8], output: &mut [u8]) {
+        output.copy_from_slice(&D::digest(data)[..output.len()]);
+    }
+}
+
+/// Hasher for BLAKE3 with its default 32 byte output. Wraps `blake3` directly
+/// because its `digest` trait impls are not covered by semver.
+pub struct Blake3Hasher;
+
+impl BlockHasher for Blake3Hasher {
+    fn digest_len(&self) -> Option<usize> {
+        Some(blake3::OUT_LEN)
+    }
+
+    fn hash(&self, data: &[u8], output: &mut [u8]) {
+        output.copy_from_slice(&blake3::hash(data).as_bytes()[..output.len()]);
+    }
+}
+
+/// Hasher for extendable-output functions which squeeze exactly as many
+/// bytes as the block has pixels
+pub struct XofHasher<X> {
+    xof: PhantomData<fn() -> X>,
+}
+
+impl<X: ExtendableOutput + Default> XofHasher<X> {
+    pub fn new() -> Self {
+        XofHasher { xof: PhantomData }
+    }
+}
+
+impl<X: ExtendableOutput + Default> Default for XofHasher<X> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<X: ExtendableOutput + Default> BlockHasher for XofHasher<X> {
+    fn digest_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn hash(&self, data: &[u8], output: &mut [u8]) {
+        let mut hasher = X::default();
+        hasher.update(data);
+        hasher.finalize_xof().read(output);
+    }
+}
+
+/// Hasher for the BLAKE3 extendable output, wrapped directly for the same
+/// reason as `Blake3Hasher`
+pub struct Blake3XofHasher;
+
+impl BlockHasher for Blake3XofHasher {
+    fn digest_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn hash(&self, data: &[u8], output: &mut [u8]) {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(data);
+        hasher.finalize_xof().fill(output);
+    }
+}
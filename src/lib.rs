@@ -0,0 +1,73 @@
+//! Create art with hash algorithms.
+//!
+//! The source image is split into blocks and every block is slightly
+//! distorted until its hash resembles the corresponding block of the target
+//! image.
+//!
+//! ```no_run
+//! use hash_art::{open_image, save_image, HashAlgorithm, HashArt};
+//! use std::path::Path;
+//!
+//! # fn main() -> hash_art::Result<()> {
+//! let source = open_image(Path::new("source.png"))?;
+//! let target = open_image(Path::new("target.png"))?;
+//! let art = HashArt::builder(source, target)
+//!     .hash(HashAlgorithm::Blake3)
+//!     .iterations(1000)
+//!     .seed(42)
+//!     .build()?;
+//! let result = art.run();
+//! save_image(&result.source, Path::new("result-source.png"))?;
+//! save_image(&result.target, Path::new("result-target.png"))?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod anneal;
+pub mod approximator;
+pub mod block;
+pub mod budget;
+pub mod checkpoint;
+pub mod color;
+pub mod diagnostics;
+pub mod distortion;
+pub mod edge;
+pub mod engine;
+pub mod enumerate;
+pub mod error;
+pub mod hash;
+pub mod mask;
+pub mod metric;
+pub mod nonce;
+pub mod preprocess;
+pub mod progress;
+pub mod refine;
+pub mod report;
+pub mod sidecar;
+pub mod verify;
+
+use image::{ImageBuffer, Luma};
+
+pub use anneal::{Cooling, TemperatureSchedule};
+pub use approximator::BlockApproximator;
+pub use block::{Block, BlockSize};
+pub use budget::{Budget, TimeBudget};
+pub use checkpoint::{BlockState, Checkpoint, CheckpointSettings, ImageDigest};
+pub use color::ColorMode;
+pub use distortion::{Distortion, DistortionRange};
+pub use edge::EdgePolicy;
+pub use engine::{
+    Approximation, BlockResult, HashArt, HashArtBuilder, Perturbation, SearchStrategy,
+};
+pub use enumerate::{BlockCounter, Counters};
+pub use error::{open_image, save_image, Error, Result};
+pub use hash::{BlockHasher, HashAlgorithm};
+pub use mask::{FrozenBlocks, TextureMask};
+pub use metric::{BlockMetric, ErrorMetric};
+pub use nonce::Nonces;
+pub use preprocess::{Fit, TargetPreprocessing};
+pub use progress::{Progress, ProgressReporter};
+pub use refine::{Refinement, Selection};
+pub use report::Summary;
+
+pub type GrayscaleImage = ImageBuffer<Luma<u8>, Vec<u8>>;
@@ -1,167 +1,627 @@
-use clap::Parser;
-use image::{ImageBuffer, Luma};
-use imageproc::map::map_colors;
-use ndarray::prelude::*;
-use ndarray_rand::rand_distr::Uniform;
-use ndarray_rand::RandomExt;
-use nshare::RefNdarray2;
-use sha2::digest::Update;
-use sha2::{Digest, Sha512};
-use std::time::Instant;
-
-const N: usize = 8;
-const DISTORTION: u8 = 2;
-
-type GrayscaleImage = ImageBuffer<Luma<u8>, Vec<u8>>;
+use clap::{Args, Parser, Subcommand};
+use hash_art::{
+    diagnostics, enumerate, nonce, open_image, report, save_image, verify, BlockSize, Checkpoint,
+    ColorMode, Cooling, Distortion, DistortionRange, EdgePolicy, Error, ErrorMetric, Fit,
+    FrozenBlocks, HashAlgorithm, HashArt, HashArtBuilder, Perturbation, ProgressReporter,
+    Refinement, SearchStrategy, Selection, TargetPreprocessing, TemperatureSchedule, TextureMask,
+};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
-struct Args {
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    /// Parses the command line, `approximate` is the default command and can
+    /// be omitted: `hash_art --source a.png --target b.png`
+    fn parse_with_default_command() -> Self {
+        let mut args: Vec<OsString> = std::env::args_os().collect();
+        let implicit = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+            arg.starts_with('-') && !matches!(arg, "-h" | "--help" | "-V" | "--version")
+        });
+        if implicit {
+            args.insert(1, "approximate".into());
+        }
+        Cli::parse_from(args)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Approximate the target image with the hashes of the source image (default)
+    Approximate(Box<ApproximateArgs>),
+    /// Re-hash a result source image and check it against a result target
+    Verify(VerifyArgs),
+}
+
+#[derive(Args, Debug)]
+struct ApproximateArgs {
     /// Implicitly using `std::str::FromStr`
     #[arg(short, long)]
-    source: std::path::PathBuf,
+    source: PathBuf,
 
     /// Implicitly using `std::str::FromStr`
     #[arg(short, long)]
-    target: std::path::PathBuf,
+    target: PathBuf,
 
     /// File to which the output image should be written
-    #[arg(long, default_value = "result-source.jpg")]
-    result_source: String,
+    #[arg(long, default_value = "result-source.png")]
+    result_source: PathBuf,
 
     /// File to which the output image should be written
-    #[arg(long, default_value = "result-target.jpg")]
-    result_target: String,
+    #[arg(long, default_value = "result-target.png")]
+    result_target: PathBuf,
 
-    /// The number of iterations to find a good approximation
-    #[arg(long, default_value_t = 100)]
-    iterations: u64,
-}
+    /// File to which an image of the error of every block is written
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// File to which the difference between the source and the result
+    /// source is written, stretched so the largest difference is white
+    #[arg(long)]
+    difference: Option<PathBuf>,
+
+    /// File to which a composite of the source, result source, target and
+    /// result target is written
+    #[arg(long)]
+    composite: Option<PathBuf>,
+
+    /// File to which the distortion mask is written, combined from --mask
+    /// and --texture-mask
+    #[arg(long)]
+    mask_output: Option<PathBuf>,
+
+    /// Allow writing the results in a format that is not lossless such as
+    /// JPEG or GIF. The saved result source will then no longer hash to the
+    /// saved result target.
+    #[arg(long)]
+    allow_lossy: bool,
+
+    /// How a target of a different size is fitted to the size of the source
+    #[arg(long, value_enum, default_value_t = Fit::None)]
+    fit: Fit,
+
+    /// Stretch the contrast of the target so that its 1st and 99th
+    /// percentile become black and white
+    #[arg(long)]
+    stretch_contrast: bool,
+
+    /// Equalize the histogram of the target towards the uniform distribution
+    /// of hash bytes
+    #[arg(long)]
+    equalize: bool,
+
+    /// The number of iterations to find a good approximation. Defaults to
+    /// 100, with a time budget it is unlimited unless given.
+    #[arg(long)]
+    iterations: Option<u64>,
+
+    /// Wall clock time of the whole run in seconds. The time is distributed
+    /// across the blocks, the best result found so far is written when it
+    /// runs out.
+    #[arg(long, value_parser = parse_seconds)]
+    time_budget: Option<Duration>,
+
+    /// Time spent searching a single block in seconds
+    #[arg(long, value_parser = parse_seconds)]
+    block_time_budget: Option<Duration>,
+
+    /// The strategy used to search for good distortions
+    #[arg(long, value_enum, default_value_t = SearchStrategy::Random)]
+    strategy: SearchStrategy,
+
+    /// The metric used to compare the hash of a block with the target block
+    #[arg(long, value_enum, default_value_t = ErrorMetric::L2)]
+    metric: ErrorMetric,
+
+    /// Start temperature of the anneal strategy, in units of the error
+    /// metric per pixel
+    #[arg(long, default_value_t = 10000.0)]
+    start_temperature: f32,
+
+    /// End temperature of the anneal strategy
+    #[arg(long, default_value_t = 100.0)]
+    end_temperature: f32,
+
+    /// How the temperature of the anneal strategy decreases
+    #[arg(long, value_enum, default_value_t = Cooling::Exponential)]
+    cooling: Cooling,
+
+    /// Number of refinement passes after the first pass, every pass searches
+    /// the selected blocks again. Defaults to 1 if blocks are selected.
+    #[arg(long, requires = "refine_selection")]
+    refine_passes: Option<usize>,
+
+    /// Refine the given number of blocks with the highest error
+    #[arg(long, group = "refine_selection")]
+    refine_worst: Option<usize>,
+
+    /// Refine all blocks whose error is above the threshold
+    #[arg(long, group = "refine_selection")]
+    refine_threshold: Option<f32>,
+
+    /// File with the counter of every block and the counter value of its
+    /// best distortion. If the file exists the search of every block
+    /// continues from its counter and keeps its best distortion, the final
+    /// counters are written back to the file. Useful with the enumerate
+    /// strategy.
+    #[arg(long)]
+    counters: Option<PathBuf>,
 
-trait BlockApproximator {
-    fn approximate(
-        &self,
-        input: &ArrayView2<u8>,
-        target: &ArrayView2<u8>,
-    ) -> (f32, Array2<u8>, Array2<u8>);
+    /// Additionally run the random strategy with the same seed and report
+    /// its error for comparison
+    #[arg(long)]
+    compare_random: bool,
+
+    /// Distort the pixels of the source or leave the source untouched and
+    /// search for a nonce that is appended to every block before hashing
+    #[arg(long, value_enum, default_value_t = Perturbation::Pixels)]
+    perturb: Perturbation,
+
+    /// Whether distortions are added to the darkened source, spread
+    /// symmetrically around the original pixels or replace the lowest bits
+    #[arg(long, value_enum, default_value_t = DistortionRange::Additive)]
+    distortion: DistortionRange,
+
+    /// Grayscale image of the size of the source that scales the distortion
+    /// of every pixel, black leaves the pixel untouched and white allows the
+    /// full amplitude
+    #[arg(long)]
+    mask: Option<PathBuf>,
+
+    /// Derive a distortion mask from the texture of the source, so flat
+    /// regions are distorted less than textured ones
+    #[arg(long)]
+    texture_mask: bool,
+
+    /// Radius of the window in which the texture is measured
+    #[arg(long, default_value_t = 2, requires = "texture_mask")]
+    texture_radius: u32,
+
+    /// Local standard deviation of the source at and above which a pixel
+    /// gets the full amplitude
+    #[arg(long, default_value_t = 16.0, requires = "texture_mask")]
+    texture_deviation: f32,
+
+    /// What is done with blocks the mask leaves no distortion
+    #[arg(long, value_enum, default_value_t = FrozenBlocks::Nonce)]
+    frozen_blocks: FrozenBlocks,
+
+    /// Largest change of a pixel by the distortion, or the number of
+    /// replaced bits for the lsb distortion
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=i64::from(Distortion::MAX_AMPLITUDE)))]
+    amplitude: u8,
+
+    /// File to which the nonces of the blocks are written in nonce mode or
+    /// for frozen blocks of a mask
+    #[arg(long, default_value = "result-nonces.txt")]
+    nonces: PathBuf,
+
+    #[command(flatten)]
+    hash: HashArgs,
+
+    /// Number of threads used to process blocks, defaults to all CPU cores
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Seed for the random distortions, runs with the same seed and inputs
+    /// produce identical results. A random seed is used if none is given.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// File to which the error, the iteration of the best distortion and the
+    /// number of hashes of every block are written together with summary
+    /// statistics. Written as JSON if the file ends in .json, else as CSV.
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Seconds between two progress lines if the output is not a terminal,
+    /// in a terminal a status line is updated continuously
+    #[arg(long, value_parser = parse_seconds, default_value = "10")]
+    progress_interval: Duration,
+
+    /// File to which the progress of the run is written at intervals, so an
+    /// interrupted run can be continued with --resume
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between two checkpoints
+    #[arg(long, value_parser = parse_seconds, default_value = "60")]
+    checkpoint_interval: Duration,
+
+    /// Continue the run that wrote the checkpoint, finished blocks are
+    /// restored and the seed of the checkpoint is used
+    #[arg(long)]
+    resume: Option<PathBuf>,
 }
 
-struct Sha512CpuApproximator {
-    iterations: u64,
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// The result source image to hash
+    #[arg(short, long)]
+    source: PathBuf,
+
+    /// The result target image the hashes are compared to
+    #[arg(short, long, required_unless_present = "output")]
+    target: Option<PathBuf>,
+
+    /// File to which the hashed result source should be written
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Nonces written by a run in nonce mode, the nonce of every block is
+    /// appended to its pixels before hashing
+    #[arg(long)]
+    nonces: Option<PathBuf>,
+
+    #[command(flatten)]
+    hash: HashArgs,
 }
 
-impl Sha512CpuApproximator {
-    fn new(iterations: u64) -> Self {
-        Sha512CpuApproximator { iterations }
-    }
+#[derive(Args, Debug)]
+struct HashArgs {
+    /// The hash algorithm, its digest length determines the default block size
+    #[arg(long, value_enum, default_value_t = HashAlgorithm::Sha512)]
+    hash: HashAlgorithm,
+
+    /// Block size as WIDTHxHEIGHT, must match the digest length unless an
+    /// extendable-output hash is used
+    #[arg(long)]
+    block_size: Option<BlockSize>,
+
+    /// How pixels at the right and bottom edge that do not fill a complete
+    /// block are handled
+    #[arg(long, value_enum, default_value_t = EdgePolicy::Partial)]
+    edge_policy: EdgePolicy,
+
+    /// Process the images in grayscale or hash every colour channel
+    #[arg(long, value_enum, default_value_t = ColorMode::Gray)]
+    color: ColorMode,
 }
 
-impl BlockApproximator for Sha512CpuApproximator {
-    fn approximate(
-        &self,
-        input: &ArrayView2<u8>,
-        target: &ArrayView2<u8>,
-    ) -> (f32, Array2<u8>, Array2<u8>) {
-        let mut best_source = Array2::<u8>::zeros((N, N));
-        let mut best_target = Array2::<u8>::zeros((N, N));
-
-        let mut error = f32::MAX;
-
-        for _ in 0..self.iterations {
-            let delta: Array2<u8> = Array::random((N, N), Uniform::new(0, DISTORTION));
-            let current_source = delta + input;
-            let input_vec = current_source.as_slice().unwrap();
-            let mut hasher = Sha512::new();
-            Update::update(&mut hasher, input_vec);
-            let result = hasher.finalize();
-
-            let current_target = Array::from_iter(result).into_shape((N, N)).unwrap();
-
-            let mut total_error = 0.0;
-            for m in 0..N {
-                for n in 0..N {
-                    let val = target[[m, n]] as f32 - current_target[[m, n]] as f32;
-                    total_error += val * val;
-                }
-            }
-
-            if error > total_error {
-                best_source = current_source;
-                best_target = current_target;
-                error = total_error;
-            }
+impl ApproximateArgs {
+    /// Applies the options shared by all runs to a builder
+    fn configure(&self, builder: HashArtBuilder, mask: Option<&DynamicImage>) -> HashArtBuilder {
+        let mut builder = builder
+            .color_mode(self.hash.color)
+            .hash(self.hash.hash)
+            .block_size(self.hash.block_size)
+            .edge_policy(self.hash.edge_policy)
+            .strategy(self.strategy)
+            .target_preprocessing(TargetPreprocessing {
+                fit: self.fit,
+                stretch_contrast: self.stretch_contrast,
+                equalize: self.equalize,
+            })
+            .perturbation(self.perturb)
+            .distortion(Distortion {
+                range: self.distortion,
+                amplitude: self.amplitude,
+            })
+            .metric(self.metric)
+            .temperature_schedule(TemperatureSchedule {
+                start: self.start_temperature,
+                end: self.end_temperature,
+                cooling: self.cooling,
+            })
+            .iterations(self.iterations.unwrap_or(100));
+        if let Some(threads) = self.threads {
+            builder = builder.threads(threads);
+        }
+        if let Some(mask) = mask {
+            builder = builder.mask(mask.clone());
         }
-        (error, best_source, best_target)
+        if self.texture_mask {
+            builder = builder.texture_mask(TextureMask {
+                radius: self.texture_radius,
+                full_deviation: self.texture_deviation,
+            });
+        }
+        builder = builder.frozen_blocks(self.frozen_blocks);
+        let selection = match (self.refine_worst, self.refine_threshold) {
+            (Some(count), _) => Some(Selection::Worst(count)),
+            (None, Some(threshold)) => Some(Selection::Above(threshold)),
+            (None, None) => None,
+        };
+        if let Some(selection) = selection {
+            builder = builder.refinement(Refinement {
+                passes: self.refine_passes.unwrap_or(1),
+                selection,
+            });
+        }
+        if let Some(budget) = self.time_budget {
+            builder = builder.time_budget(budget);
+        }
+        if let Some(budget) = self.block_time_budget {
+            builder = builder.block_time_budget(budget);
+        }
+        if self.iterations.is_none()
+            && (self.time_budget.is_some() || self.block_time_budget.is_some())
+        {
+            builder = builder.iterations(u64::MAX);
+        }
+        builder
+    }
+
+    /// All image files written by the run
+    fn image_outputs(&self) -> Vec<&Path> {
+        [&self.result_source, &self.result_target]
+            .into_iter()
+            .chain(&self.heatmap)
+            .chain(&self.difference)
+            .chain(&self.composite)
+            .chain(&self.mask_output)
+            .map(PathBuf::as_path)
+            .collect()
+    }
+
+    /// Output files whose format does not preserve the exact pixel values
+    fn lossy_outputs(&self) -> Vec<&Path> {
+        [&self.result_source, &self.result_target]
+            .into_iter()
+            .map(PathBuf::as_path)
+            .filter(|path| is_lossy(path))
+            .collect()
     }
 }
 
-fn approximate_image(
-    input: &mut GrayscaleImage,
-    target: &mut GrayscaleImage,
-    approximator: &dyn BlockApproximator,
-) -> (GrayscaleImage, GrayscaleImage) {
-    let mut result_source = image::imageops::grayscale(input);
-    let mut result_target = image::imageops::grayscale(input);
-    let dim = input.dimensions();
-    let mut total_error = 0.0;
-    println!("image dimensions {:?}", dim);
-    for i in 0..(dim.0 / 8) {
-        for j in 0..(dim.1 / 8) {
-            let input_block = image::imageops::crop(input, i * 8, j * 8, 8, 8).to_image();
-            let target_block = image::imageops::crop(target, i * 8, j * 8, 8, 8).to_image();
-
-            let (error, source, target) =
-                approximator.approximate(&input_block.ref_ndarray2(), &target_block.ref_ndarray2());
-            total_error += error;
-
-            for n in 0..8 {
-                for m in 0..8 {
-                    result_source.put_pixel(
-                        i * 8 + m,
-                        j * 8 + n,
-                        image::Luma([source[(n as usize, m as usize)]]),
-                    );
-                    result_target.put_pixel(
-                        i * 8 + m,
-                        j * 8 + n,
-                        image::Luma([target[(n as usize, m as usize)]]),
-                    );
-                }
-            }
-        }
+fn parse_seconds(seconds: &str) -> Result<Duration, String> {
+    let seconds: f64 = seconds
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    Duration::try_from_secs_f64(seconds).map_err(|e| e.to_string())
+}
+
+/// Checks that an image can be written to the file, so a typo in the
+/// extension is reported before the search instead of after it
+fn check_output_format(path: &Path) -> hash_art::Result<()> {
+    match ImageFormat::from_path(path) {
+        Ok(format) if format.writing_enabled() => Ok(()),
+        _ => Err(Error::InvalidConfig(format!(
+            "cannot write an image to {path:?}, its extension is not a supported image format"
+        ))),
     }
-    println!("Total error: {total_error}");
-    (result_source, result_target)
 }
 
-fn main() {
-    let args = Args::parse();
+/// Whether the format of the file may change pixel values. Formats that are
+/// not known to be lossless count as lossy, e.g. GIF quantises colours to a
+/// palette.
+fn is_lossy(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| {
+        !matches!(
+            format,
+            ImageFormat::Png
+                | ImageFormat::Bmp
+                | ImageFormat::Tiff
+                | ImageFormat::Pnm
+                | ImageFormat::Tga
+                | ImageFormat::Qoi
+                | ImageFormat::WebP
+        )
+    })
+}
+
+fn main() -> ExitCode {
+    let result = match Cli::parse_with_default_command().command {
+        Command::Approximate(args) => approximate(*args).map(|()| ExitCode::SUCCESS),
+        Command::Verify(args) => verify(args),
+    };
+    result.unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        ExitCode::from(error.exit_code())
+    })
+}
+
+fn approximate(args: ApproximateArgs) -> hash_art::Result<()> {
     println!("{args:?}");
 
+    for path in args.image_outputs() {
+        check_output_format(path)?;
+    }
+    let lossy_outputs = args.lossy_outputs();
+    if !lossy_outputs.is_empty() {
+        if !args.allow_lossy {
+            return Err(Error::InvalidConfig(format!(
+                "Refusing to write {lossy_outputs:?} in a lossy format, the hashes would not be reproducible. Use a lossless format like png or pass --allow-lossy"
+            )));
+        }
+        println!(
+            "Warning: {lossy_outputs:?} use a lossy format, the hashes will not be reproducible"
+        );
+    }
+
     println!("Reading source file: {:?}", args.source);
-    let source = image::open(args.source).unwrap();
-    let mut source = map_colors(&source, |p| Luma([p[0].saturating_sub(DISTORTION)]));
+    let source = open_image(&args.source)?;
 
     println!("Reading target file: {:?}", args.target);
-    let mut target = image::open(args.target).unwrap().to_luma8();
+    let target = open_image(&args.target)?;
+    let source_dimensions = source.dimensions();
+    println!("image dimensions {:?}", source_dimensions);
 
-    if target.dimensions() != source.dimensions() {
-        println!("source and target image must have same size");
-        return;
-    }
+    let mask = match &args.mask {
+        Some(path) => {
+            println!("Reading mask file: {:?}", path);
+            Some(open_image(path)?)
+        }
+        None => None,
+    };
 
-    /*if target.dimensions().0 % 8 != 0 || target.dimensions().1 % 8 != 0 {
-        println!("Image dimension must be a multiple of 8");
-        return;
-    }*/
+    let mut builder = args.configure(
+        HashArt::builder(source.clone(), target.clone()),
+        mask.as_ref(),
+    );
+    if let Some(seed) = args.seed {
+        builder = builder.seed(seed);
+    }
+    if let Some(path) = args.counters.as_ref().filter(|path| path.exists()) {
+        println!("Reading counters from file: {:?}", path);
+        builder = builder.counters(enumerate::read_counters(path)?);
+    }
+    if let Some(path) = &args.resume {
+        println!("Resuming from checkpoint: {:?}", path);
+        builder = builder.resume(Checkpoint::read(path)?);
+    }
+    if let Some(path) = &args.checkpoint {
+        builder = builder.checkpoint(path, args.checkpoint_interval);
+    }
+    let reporter = Arc::new(ProgressReporter::new(args.progress_interval));
+    let progress = Arc::clone(&reporter);
+    builder = builder.on_progress(move |p| progress.report(p));
+    let art = builder.build()?;
+    println!("block size {}", art.block_size());
+    println!(
+        "edge policy {:?}: {}",
+        art.edge_policy(),
+        art.edge_policy()
+            .describe(source_dimensions, art.block_size())
+    );
+    println!("Using seed {}", art.seed());
+    if args.resume.is_some() {
+        println!(
+            "{} blocks restored from the checkpoint",
+            art.resumed_blocks()
+        );
+    }
 
     let now = Instant::now();
-    let approximator = Sha512CpuApproximator::new(args.iterations);
-    let (result_source, result_target) = approximate_image(&mut source, &mut target, &approximator);
+    let result = art.run();
+    reporter.finish();
+    println!("Total error: {}", result.error);
+    println!(
+        "PSNR of the result source: {:.2} dB",
+        diagnostics::psnr(&source, &result.source, art.color_mode())
+    );
 
-    println!("Writing result source to file: {}", args.result_source);
-    result_source.save(args.result_source).unwrap();
-    println!("Writing result target to file: {}", args.result_target);
-    result_target.save(args.result_target).unwrap();
+    if args.compare_random {
+        let random = args
+            .configure(
+                HashArt::builder(source.clone(), target.clone()),
+                mask.as_ref(),
+            )
+            .strategy(SearchStrategy::Random)
+            .seed(art.seed())
+            .build()?
+            .run();
+        println!(
+            "Total error of the random strategy with the same seed: {} ({:+.2}%)",
+            random.error,
+            (result.error / random.error - 1.0) * 100.0
+        );
+    }
+
+    if let Some(path) = &args.report {
+        println!("Writing report to file: {:?}", path);
+        report::write_report(path, &result)?;
+    }
+    if let Some(path) = &args.checkpoint {
+        println!("Writing checkpoint to file: {:?}", path);
+        art.write_checkpoint()?;
+    }
+    if let Some(path) = &args.counters {
+        println!("Writing counters to file: {:?}", path);
+        enumerate::write_counters(path, &result.blocks)?;
+    }
+    // Frozen blocks of a mask may have nonces as well
+    if result.blocks.iter().any(|block| block.nonce.is_some()) {
+        println!("Writing nonces to file: {:?}", args.nonces);
+        nonce::write_nonces(&args.nonces, &result.blocks)?;
+    }
+
+    if let Some(path) = &args.heatmap {
+        println!("Writing error heatmap to file: {:?}", path);
+        let heatmap = diagnostics::error_heatmap(&result, art.block_size());
+        save_image(&heatmap.into(), path)?;
+    }
+    if let Some(path) = &args.difference {
+        println!("Writing difference image to file: {:?}", path);
+        let difference = diagnostics::difference(&source, &result.source, art.color_mode());
+        save_image(&difference, path)?;
+    }
+    if let Some(path) = &args.composite {
+        println!("Writing composite image to file: {:?}", path);
+        let composite =
+            diagnostics::composite(&source, &result.source, &art.target(), &result.target);
+        save_image(&composite.into(), path)?;
+    }
+    if let Some(path) = &args.mask_output {
+        println!("Writing distortion mask to file: {:?}", path);
+        save_image(&art.mask().clone().into(), path)?;
+    }
+
+    println!("Writing result source to file: {:?}", args.result_source);
+    save_image(&result.source, &args.result_source)?;
+    println!("Writing result target to file: {:?}", args.result_target);
+    save_image(&result.target, &args.result_target)?;
     println!("Execution time: {}ms", now.elapsed().as_millis());
+    Ok(())
+}
+
+/// Succeeds with a failure exit code if blocks do not match
+fn verify(args: VerifyArgs) -> hash_art::Result<ExitCode> {
+    println!("{args:?}");
+
+    let (hasher, block_size) = args.hash.hash.hasher_for(args.hash.block_size)?;
+    if let Some(output) = &args.output {
+        check_output_format(output)?;
+    }
+
+    println!("Reading result source file: {:?}", args.source);
+    let source = open_image(&args.source)?;
+    let nonces = match &args.nonces {
+        Some(path) => {
+            println!("Reading nonces from file: {:?}", path);
+            Some(nonce::read_nonces(path)?)
+        }
+        None => None,
+    };
+    let (color_mode, edge_policy) = (args.hash.color, args.hash.edge_policy);
+    let hashed = verify::hash_image(
+        &source,
+        color_mode,
+        block_size,
+        edge_policy,
+        hasher.as_ref(),
+        nonces.as_ref(),
+    );
+
+    if let Some(output) = &args.output {
+        println!("Writing hashed result source to file: {:?}", output);
+        save_image(&hashed, output)?;
+    }
+
+    let Some(target) = args.target else {
+        return Ok(ExitCode::SUCCESS);
+    };
+    println!("Reading result target file: {:?}", target);
+    let target = open_image(&target)?;
+    if target.dimensions() != source.dimensions() {
+        return Err(Error::DimensionMismatch(
+            source.dimensions(),
+            target.dimensions(),
+        ));
+    }
+
+    let mismatches = verify::compare(&hashed, &target, color_mode, block_size, edge_policy);
+    for mismatch in &mismatches {
+        println!(
+            "Block at ({}, {}) of channel {} differs in {} pixels",
+            mismatch.x, mismatch.y, mismatch.channel, mismatch.differing_pixels
+        );
+    }
+    let blocks = edge_policy.blocks(source.dimensions(), block_size).len() * color_mode.channels();
+    if mismatches.is_empty() {
+        println!("All {blocks} blocks match");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("{} of {blocks} blocks do not match", mismatches.len());
+        Ok(ExitCode::FAILURE)
+    }
 }
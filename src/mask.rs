@@ -0,0 +1,134 @@
+//! Distortion masks restrict where the source may be distorted, so that
+//! e.g. faces or logos stay pristine while busy texture absorbs the changes
+
+use crate::approximator::{BlockApproximator, BlockCandidate, BlockRng};
+use crate::budget::Budget;
+use crate::hash::BlockHasher;
+use crate::metric::BlockMetric;
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::Luma;
+use imageproc::map::map_colors2;
+use ndarray::prelude::*;
+
+/// What is done with blocks whose pixels the mask leaves no distortion
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FrozenBlocks {
+    /// The pixels stay untouched and a nonce is searched instead, like with
+    /// [`crate::Perturbation::Nonce`]
+    #[default]
+    Nonce,
+    /// The untouched block is hashed once
+    Skip,
+}
+
+/// Derives a distortion mask from the texture of the source. Distortions
+/// are hard to see in textured regions and easy to see in flat ones, so the
+/// mask value of a pixel grows with the standard deviation of the pixels
+/// around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureMask {
+    /// The window around every pixel is `2 * radius + 1` pixels wide and high
+    pub radius: u32,
+    /// Standard deviation at and above which a pixel gets the full amplitude
+    pub full_deviation: f32,
+}
+
+impl TextureMask {
+    /// Computes the mask of a grayscale source
+    pub fn compute(&self, source: &GrayscaleImage) -> GrayscaleImage {
+        let (width, height) = source.dimensions();
+        // Summed area tables of the pixels and their squares, with a leading
+        // row and column of zeros
+        let stride = width as usize + 1;
+        let mut sums = vec![0.0f64; stride * (height as usize + 1)];
+        let mut squares = sums.clone();
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let pixel = f64::from(source.get_pixel(x as u32, y as u32)[0]);
+                let (index, above, left, diagonal) = (
+                    (y + 1) * stride + x + 1,
+                    y * stride + x + 1,
+                    (y + 1) * stride + x,
+                    y * stride + x,
+                );
+                sums[index] = pixel + sums[above] + sums[left] - sums[diagonal];
+                squares[index] = pixel * pixel + squares[above] + squares[left] - squares[diagonal];
+            }
+        }
+        let area = |table: &[f64], left: u32, top: u32, right: u32, bottom: u32| {
+            let (left, top, right, bottom) =
+                (left as usize, top as usize, right as usize, bottom as usize);
+            table[bottom * stride + right]
+                - table[top * stride + right]
+                - table[bottom * stride + left]
+                + table[top * stride + left]
+        };
+        GrayscaleImage::from_fn(width, height, |x, y| {
+            // The window is clipped at the edges of the image
+            let (left, top) = (x.saturating_sub(self.radius), y.saturating_sub(self.radius));
+            let right = (x + self.radius + 1).min(width);
+            let bottom = (y + self.radius + 1).min(height);
+            let count = f64::from((right - left) * (bottom - top));
+            let mean = area(&sums, left, top, right, bottom) / count;
+            let variance = area(&squares, left, top, right, bottom) / count - mean * mean;
+            let deviation = variance.max(0.0).sqrt() as f32;
+            Luma([(deviation / self.full_deviation * 255.0)
+                .clamp(0.0, 255.0)
+                .round() as u8])
+        })
+    }
+}
+
+impl Default for TextureMask {
+    fn default() -> Self {
+        TextureMask {
+            radius: 2,
+            full_deviation: 16.0,
+        }
+    }
+}
+
+/// Combines two masks, a pixel may only be distorted as far as both allow
+pub fn combine(mask: &GrayscaleImage, other: &GrayscaleImage) -> GrayscaleImage {
+    map_colors2(mask, other, |a, b| {
+        Luma([(u16::from(a[0]) * u16::from(b[0]) / 255) as u8])
+    })
+}
+
+/// Hashes the block as it is, used for frozen blocks that are skipped
+pub struct UnchangedApproximator {
+    hasher: Box<dyn BlockHasher>,
+    metric: Box<dyn BlockMetric>,
+}
+
+impl UnchangedApproximator {
+    pub fn new(hasher: Box<dyn BlockHasher>, metric: Box<dyn BlockMetric>) -> Self {
+        UnchangedApproximator { hasher, metric }
+    }
+}
+
+impl BlockApproximator for UnchangedApproximator {
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        _mask: &ArrayView2<u8>,
+        _budget: &Budget,
+        _rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let source = input.to_owned();
+        let mut output = vec![0; source.len()];
+        self.hasher.hash(source.as_slice().unwrap(), &mut output);
+        let hashed = Array::from_shape_vec(input.dim(), output).unwrap();
+        *counter += 1;
+        BlockCandidate {
+            error: self.metric.error(target, &hashed.view()),
+            iteration: 1,
+            source,
+            target: hashed,
+            nonce: None,
+        }
+    }
+}
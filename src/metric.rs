@@ -0,0 +1,127 @@
+use clap::ValueEnum;
+use ndarray::prelude::*;
+
+/// Metrics to compare the hash of a source block with the target block
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorMetric {
+    /// Sum of the absolute differences
+    L1,
+    /// Sum of the squared differences
+    #[default]
+    L2,
+    /// Largest absolute difference of any pixel
+    MaxAbs,
+    /// One minus the structural similarity of the whole block
+    Ssim,
+    /// Sum of the squared differences, weighted higher at edges of the target
+    Gradient,
+}
+
+impl ErrorMetric {
+    pub fn metric(self) -> Box<dyn BlockMetric> {
+        match self {
+            ErrorMetric::L1 => Box::new(L1),
+            ErrorMetric::L2 => Box::new(L2),
+            ErrorMetric::MaxAbs => Box::new(MaxAbs),
+            ErrorMetric::Ssim => Box::new(Ssim),
+            ErrorMetric::Gradient => Box::new(Gradient),
+        }
+    }
+}
+
+/// Error between a target block and the hash of a source block, lower is
+/// better and 0 is a perfect match
+pub trait BlockMetric: Send + Sync {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32;
+}
+
+fn differences<'a>(
+    target: &'a ArrayView2<u8>,
+    actual: &'a ArrayView2<u8>,
+) -> impl Iterator<Item = f32> + 'a {
+    target
+        .iter()
+        .zip(actual)
+        .map(|(expected, actual)| *expected as f32 - *actual as f32)
+}
+
+pub struct L1;
+
+impl BlockMetric for L1 {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32 {
+        differences(target, actual).map(f32::abs).sum()
+    }
+}
+
+pub struct L2;
+
+impl BlockMetric for L2 {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32 {
+        differences(target, actual).map(|d| d * d).sum()
+    }
+}
+
+pub struct MaxAbs;
+
+impl BlockMetric for MaxAbs {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32 {
+        differences(target, actual)
+            .map(f32::abs)
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Structural similarity computed over the whole block as a single window
+pub struct Ssim;
+
+impl Ssim {
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+}
+
+impl BlockMetric for Ssim {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32 {
+        let n = target.len() as f32;
+        let mean = |block: &ArrayView2<u8>| block.iter().map(|&p| p as f32).sum::<f32>() / n;
+        let (mean_t, mean_a) = (mean(target), mean(actual));
+        let (mut var_t, mut var_a, mut covariance) = (0.0, 0.0, 0.0);
+        for (&t, &a) in target.iter().zip(actual) {
+            let (dt, da) = (t as f32 - mean_t, a as f32 - mean_a);
+            var_t += dt * dt;
+            var_a += da * da;
+            covariance += dt * da;
+        }
+        let (var_t, var_a, covariance) = (var_t / n, var_a / n, covariance / n);
+        let ssim = ((2.0 * mean_t * mean_a + Self::C1) * (2.0 * covariance + Self::C2))
+            / ((mean_t * mean_t + mean_a * mean_a + Self::C1) * (var_t + var_a + Self::C2));
+        1.0 - ssim
+    }
+}
+
+/// Squared differences weighted by the gradient magnitude of the target, so
+/// errors at edges of the target count more than errors in flat areas
+pub struct Gradient;
+
+impl Gradient {
+    /// Additional weight of a pixel at the strongest possible edge
+    const EDGE_WEIGHT: f32 = 4.0;
+}
+
+impl BlockMetric for Gradient {
+    fn error(&self, target: &ArrayView2<u8>, actual: &ArrayView2<u8>) -> f32 {
+        let (rows, cols) = target.dim();
+        let mut total_error = 0.0;
+        for ((row, col), &expected) in target.indexed_iter() {
+            // Central differences, clamped at the border of the block
+            let dx = target[(row, (col + 1).min(cols - 1))] as f32
+                - target[(row, col.saturating_sub(1))] as f32;
+            let dy = target[((row + 1).min(rows - 1), col)] as f32
+                - target[(row.saturating_sub(1), col)] as f32;
+            let magnitude = (dx * dx + dy * dy).sqrt() / 255.0;
+            let weight = 1.0 + Self::EDGE_WEIGHT * magnitude.min(1.0);
+            let val = expected as f32 - actual[(row, col)] as f32;
+            total_error += weight * val * val;
+        }
+        total_error
+    }
+}
@@ -0,0 +1,136 @@
+use crate::approximator::{BlockApproximator, BlockCandidate, BlockRng};
+use crate::budget::Budget;
+use crate::engine::BlockResult;
+use crate::error::Result;
+use crate::hash::BlockHasher;
+use crate::metric::BlockMetric;
+use crate::sidecar::{self, BlockKey};
+use ndarray::prelude::*;
+use ndarray_rand::rand::Rng;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Nonces of the blocks
+pub type Nonces = HashMap<BlockKey, u64>;
+
+/// Appends the nonce to the pixels of a block, this is the input of the hash
+/// in nonce mode
+pub fn with_nonce(pixels: &[u8], nonce: u64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(pixels.len() + 8);
+    data.extend_from_slice(pixels);
+    data.extend_from_slice(&nonce.to_le_bytes());
+    data
+}
+
+/// Leaves the source block untouched and searches for a nonce instead. The
+/// hash input is the pixels of the block followed by the nonce as 8 little
+/// endian bytes.
+pub struct NonceApproximator {
+    hasher: Box<dyn BlockHasher>,
+    metric: Box<dyn BlockMetric>,
+    /// Draw random nonces instead of counting up from the block counter
+    random: bool,
+}
+
+impl NonceApproximator {
+    pub fn new(hasher: Box<dyn BlockHasher>, metric: Box<dyn BlockMetric>, random: bool) -> Self {
+        NonceApproximator {
+            hasher,
+            metric,
+            random,
+        }
+    }
+}
+
+impl BlockApproximator for NonceApproximator {
+    fn approximate(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        _mask: &ArrayView2<u8>,
+        budget: &Budget,
+        rng: &mut BlockRng,
+        counter: &mut u64,
+    ) -> BlockCandidate {
+        let shape = input.dim();
+        let mut data = with_nonce(input.to_owned().as_slice().unwrap(), 0);
+        let nonce_start = data.len() - 8;
+        let mut best_target = Array2::<u8>::zeros(shape);
+        let mut best_nonce = 0;
+        let mut output = vec![0; shape.0 * shape.1];
+
+        let mut error = f32::MAX;
+        let mut best_iteration = 0;
+
+        let mut iteration = 0;
+        while !budget.exhausted(iteration) {
+            iteration += 1;
+            let nonce = if self.random { rng.gen() } else { *counter };
+            *counter += 1;
+            data[nonce_start..].copy_from_slice(&nonce.to_le_bytes());
+            self.hasher.hash(&data, &mut output);
+
+            let current_target = ArrayView::from_shape(shape, &output).unwrap();
+            let total_error = self.metric.error(target, &current_target);
+
+            if error > total_error {
+                best_target = current_target.to_owned();
+                best_nonce = nonce;
+                error = total_error;
+                best_iteration = iteration;
+            }
+        }
+        BlockCandidate {
+            error,
+            iteration: best_iteration,
+            source: input.to_owned(),
+            target: best_target,
+            nonce: Some(best_nonce),
+        }
+    }
+
+    fn replay(
+        &self,
+        input: &ArrayView2<u8>,
+        target: &ArrayView2<u8>,
+        _mask: &ArrayView2<u8>,
+        counter: u64,
+    ) -> Option<BlockCandidate> {
+        if self.random {
+            return None;
+        }
+        let pixels = input.to_owned();
+        let mut output = vec![0; pixels.len()];
+        self.hasher.hash(
+            &with_nonce(pixels.as_slice().unwrap(), counter),
+            &mut output,
+        );
+        let hashed = Array::from_shape_vec(input.dim(), output).unwrap();
+        Some(BlockCandidate {
+            error: self.metric.error(target, &hashed.view()),
+            iteration: 0,
+            source: pixels,
+            target: hashed,
+            nonce: Some(counter),
+        })
+    }
+}
+
+/// Reads nonces written by [`write_nonces`]
+pub fn read_nonces(path: &Path) -> Result<Nonces> {
+    let nonces = sidecar::read(path)?;
+    Ok(nonces
+        .into_iter()
+        .map(|(key, values)| (key, values[0]))
+        .collect())
+}
+
+/// Writes the nonce of every block
+pub fn write_nonces(path: &Path, blocks: &[BlockResult]) -> Result<()> {
+    let nonces = blocks.iter().filter_map(|block| {
+        block
+            .nonce
+            .map(|nonce| ((block.channel, block.x, block.y), [nonce]))
+    });
+    sidecar::write(path, ["nonce"], nonces)
+}
@@ -0,0 +1,101 @@
+//! Preprocessing of the target image before it is approximated
+
+use crate::color::ColorMode;
+use crate::GrayscaleImage;
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use imageproc::contrast::{equalize_histogram, stretch_contrast};
+use imageproc::stats::percentile;
+
+/// How a target of a different size is fitted to the size of the source
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fit {
+    /// The target must have the size of the source
+    #[default]
+    None,
+    /// The target is resized to the size of the source, ignoring its aspect
+    /// ratio
+    Resize,
+    /// The target is scaled to cover the source and the overhang is cropped
+    /// evenly on both sides
+    Crop,
+    /// The target is scaled to fit into the source and the remaining area is
+    /// filled with mid gray, the mean value of a hash
+    Letterbox,
+}
+
+/// Steps applied to the target before it is approximated
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TargetPreprocessing {
+    pub fit: Fit,
+    /// Stretch every plane linearly so that its 1st and 99th percentile
+    /// become black and white
+    pub stretch_contrast: bool,
+    /// Equalize the histogram of every plane. Hash bytes are uniformly
+    /// distributed, so a target with a uniform histogram wastes fewer
+    /// iterations on values that are hard to reach, like large areas of
+    /// pure black or white.
+    pub equalize: bool,
+}
+
+impl TargetPreprocessing {
+    /// Adjusts the planes of the target and fits it to the given dimensions.
+    /// The planes are adjusted first, so the area filled by
+    /// [`Fit::Letterbox`] stays mid gray.
+    pub fn apply(
+        &self,
+        target: &DynamicImage,
+        dimensions: (u32, u32),
+        color_mode: ColorMode,
+    ) -> DynamicImage {
+        if !self.stretch_contrast && !self.equalize {
+            return self.fit(target, dimensions);
+        }
+        let planes = color_mode
+            .split(target)
+            .iter()
+            .map(|plane| self.adjust(plane))
+            .collect();
+        self.fit(&color_mode.merge(planes), dimensions)
+    }
+
+    /// Fits the target to the given dimensions, returns the target unchanged
+    /// for [`Fit::None`]
+    fn fit(&self, target: &DynamicImage, (width, height): (u32, u32)) -> DynamicImage {
+        if target.dimensions() == (width, height) {
+            return target.clone();
+        }
+        match self.fit {
+            Fit::None => target.clone(),
+            Fit::Resize => target.resize_exact(width, height, FilterType::Lanczos3),
+            Fit::Crop => target.resize_to_fill(width, height, FilterType::Lanczos3),
+            Fit::Letterbox => {
+                let scaled = target
+                    .resize(width, height, FilterType::Lanczos3)
+                    .to_rgba8();
+                let mut canvas = RgbaImage::from_pixel(width, height, Rgba([128, 128, 128, 255]));
+                let x = (width - scaled.width()) / 2;
+                let y = (height - scaled.height()) / 2;
+                imageops::overlay(&mut canvas, &scaled, x.into(), y.into());
+                DynamicImage::ImageRgba8(canvas)
+            }
+        }
+    }
+
+    /// Adjusts the values of a plane of the target
+    fn adjust(&self, plane: &GrayscaleImage) -> GrayscaleImage {
+        let mut plane = plane.clone();
+        if self.stretch_contrast {
+            let (lower, upper) = (percentile(&plane, 1), percentile(&plane, 99));
+            // A flat plane has no contrast to stretch
+            if upper > lower {
+                plane = stretch_contrast(&plane, lower, upper);
+            }
+        }
+        if self.equalize {
+            plane = equalize_histogram(&plane);
+        }
+        plane
+    }
+}
@@ -0,0 +1,196 @@
+use crate::sidecar::BlockKey;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Snapshot of the progress of a run, passed to the progress callback
+#[derive(Clone, Debug)]
+pub struct Progress {
+    /// Number of blocks finished at least once, over all planes
+    pub blocks_done: usize,
+    pub blocks_total: usize,
+    /// Mean error of the finished blocks
+    pub mean_error: f32,
+    /// Hashes computed in this run so far
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn hashes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            self.hashes as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimated time until every block is finished once, refinement passes
+    /// are not included
+    pub fn eta(&self) -> Option<Duration> {
+        if self.blocks_done == 0 {
+            return None;
+        }
+        let remaining = self.blocks_total.saturating_sub(self.blocks_done);
+        Some(
+            self.elapsed
+                .mul_f64(remaining as f64 / self.blocks_done as f64),
+        )
+    }
+}
+
+pub(crate) type ProgressCallback = Box<dyn Fn(&Progress) + Send + Sync>;
+
+/// Latest error and hash count of every finished block
+struct Totals {
+    start: Instant,
+    blocks: HashMap<BlockKey, (f32, u64)>,
+    error: f64,
+    hashes: u64,
+}
+
+impl Totals {
+    fn new() -> Self {
+        Totals {
+            start: Instant::now(),
+            blocks: HashMap::new(),
+            error: 0.0,
+            hashes: 0,
+        }
+    }
+}
+
+/// Keeps running totals of the finished blocks and calls the progress
+/// callback whenever a block is finished
+pub(crate) struct ProgressTracker {
+    callback: ProgressCallback,
+    blocks_total: usize,
+    totals: Mutex<Totals>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(callback: ProgressCallback, blocks_total: usize) -> Self {
+        ProgressTracker {
+            callback,
+            blocks_total,
+            totals: Mutex::new(Totals::new()),
+        }
+    }
+
+    /// Clears the totals at the start of a run
+    pub(crate) fn reset(&self) {
+        *self.totals.lock().unwrap() = Totals::new();
+    }
+
+    /// Records the current error and hash count of a block, replacing the
+    /// values of a previous pass
+    pub(crate) fn update(&self, key: BlockKey, error: f32, hashes: u64) {
+        let progress = {
+            let mut totals = self.totals.lock().unwrap();
+            if let Some((old_error, old_hashes)) = totals.blocks.insert(key, (error, hashes)) {
+                totals.error -= f64::from(old_error);
+                totals.hashes -= old_hashes;
+            }
+            totals.error += f64::from(error);
+            totals.hashes += hashes;
+            Progress {
+                blocks_done: totals.blocks.len(),
+                blocks_total: self.blocks_total,
+                mean_error: (totals.error / totals.blocks.len() as f64) as f32,
+                hashes: totals.hashes,
+                elapsed: totals.start.elapsed(),
+            }
+        };
+        (self.callback)(&progress);
+    }
+}
+
+/// Prints progress to stdout. In a terminal a single status line is
+/// updated in place, otherwise a log line is printed at every interval.
+/// The latest progress is always printed when the run finishes.
+pub struct ProgressReporter {
+    terminal: bool,
+    interval: Duration,
+    state: Mutex<ReporterState>,
+}
+
+#[derive(Default)]
+struct ReporterState {
+    printed: Option<Instant>,
+    /// Latest progress if it has not been printed yet
+    pending: Option<Progress>,
+}
+
+impl ProgressReporter {
+    pub fn new(interval: Duration) -> Self {
+        ProgressReporter {
+            terminal: io::stdout().is_terminal(),
+            interval,
+            state: Mutex::new(ReporterState::default()),
+        }
+    }
+
+    pub fn report(&self, progress: &Progress) {
+        // Redraw the status line often enough to look live
+        let interval = if self.terminal {
+            Duration::from_millis(100)
+        } else {
+            self.interval
+        };
+        let mut state = self.state.lock().unwrap();
+        if state
+            .printed
+            .is_some_and(|printed| printed.elapsed() < interval)
+        {
+            state.pending = Some(progress.clone());
+            return;
+        }
+        state.printed = Some(Instant::now());
+        state.pending = None;
+        self.print(progress);
+    }
+
+    /// Prints the latest progress if it was skipped by the interval and ends
+    /// the status line in a terminal
+    pub fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(progress) = state.pending.take() {
+            self.print(&progress);
+        }
+        if self.terminal && state.printed.is_some() {
+            println!();
+        }
+    }
+
+    fn print(&self, progress: &Progress) {
+        let eta = progress
+            .eta()
+            .map_or_else(|| "-".to_string(), format_duration);
+        let line = format!(
+            "blocks {}/{} ({:.1}%), mean error {:.1}, {:.0} hashes/s, ETA {eta}",
+            progress.blocks_done,
+            progress.blocks_total,
+            100.0 * progress.blocks_done as f64 / progress.blocks_total.max(1) as f64,
+            progress.mean_error,
+            progress.hashes_per_second()
+        );
+        let mut stdout = io::stdout().lock();
+        if self.terminal {
+            let _ = write!(stdout, "\r\x1b[K{line}");
+            let _ = stdout.flush();
+        } else {
+            let _ = writeln!(stdout, "{line}");
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s"),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
@@ -0,0 +1,105 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Selects the blocks that are searched again in a refinement pass
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Selection {
+    /// The given number of blocks with the highest error
+    Worst(usize),
+    /// All blocks whose error is above the threshold
+    Above(f32),
+}
+
+/// Additional passes that spend more effort on the blocks with the highest
+/// error after the first pass over all blocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Refinement {
+    pub passes: usize,
+    pub selection: Selection,
+}
+
+/// Error of a block, ordered so that a [`BinaryHeap`] yields the block with
+/// the highest error first. Blocks with the same error are yielded in block
+/// order.
+#[derive(Clone, Copy, Debug)]
+pub struct RankedBlock {
+    pub error: f32,
+    /// Index of the block across all planes
+    pub index: usize,
+}
+
+impl PartialEq for RankedBlock {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedBlock {}
+
+impl PartialOrd for RankedBlock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedBlock {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.error
+            .total_cmp(&other.error)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl Selection {
+    /// Removes the selected blocks from the queue, worst block first
+    pub fn take(&self, queue: &mut BinaryHeap<RankedBlock>) -> Vec<RankedBlock> {
+        let mut selected = Vec::new();
+        while let Some(worst) = queue.peek() {
+            let done = match *self {
+                Selection::Worst(count) => selected.len() >= count,
+                Selection::Above(threshold) => worst.error <= threshold,
+            };
+            if done {
+                break;
+            }
+            selected.extend(queue.pop());
+        }
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(errors: &[f32]) -> BinaryHeap<RankedBlock> {
+        errors
+            .iter()
+            .enumerate()
+            .map(|(index, &error)| RankedBlock { error, index })
+            .collect()
+    }
+
+    fn indices(blocks: &[RankedBlock]) -> Vec<usize> {
+        blocks.iter().map(|block| block.index).collect()
+    }
+
+    #[test]
+    fn take_worst_first() {
+        let mut queue = queue(&[3.0, 7.0, 1.0, 7.0, 5.0]);
+        // Blocks with the same error are taken in block order
+        let selected = Selection::Worst(3).take(&mut queue);
+        assert_eq!(indices(&selected), [1, 3, 4]);
+        assert_eq!(queue.len(), 2);
+        let rest = Selection::Worst(10).take(&mut queue);
+        assert_eq!(indices(&rest), [0, 2]);
+    }
+
+    #[test]
+    fn take_above_threshold() {
+        let mut queue = queue(&[3.0, 7.0, 1.0, 5.0]);
+        let selected = Selection::Above(3.0).take(&mut queue);
+        assert_eq!(indices(&selected), [1, 3]);
+        assert_eq!(queue.peek().map(|block| block.index), Some(0));
+    }
+}
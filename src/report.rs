@@ -0,0 +1,214 @@
+//! Per-block reports of a run for later analysis. Reports are written as CSV
+//! or, for paths ending in `.json`, as JSON.
+
+use crate::engine::{Approximation, BlockResult};
+use crate::error::{Error, Result};
+use std::fs;
+use std::path::Path;
+
+/// Summary statistics of the block errors and the hashing effort of a run
+#[derive(Clone, Debug, PartialEq)]
+pub struct Summary {
+    pub blocks: usize,
+    pub total_error: f64,
+    pub mean_error: f64,
+    pub min_error: f64,
+    pub median_error: f64,
+    pub p90_error: f64,
+    pub p99_error: f64,
+    pub max_error: f64,
+    /// Hashes computed in this run over all blocks
+    pub hashes: u64,
+    pub seconds: f64,
+    pub hashes_per_second: f64,
+}
+
+impl Summary {
+    pub fn new(approximation: &Approximation) -> Self {
+        let mut errors: Vec<f64> = approximation
+            .blocks
+            .iter()
+            .map(|block| f64::from(block.error))
+            .collect();
+        errors.sort_by(f64::total_cmp);
+        let total_error: f64 = errors.iter().sum();
+        let hashes = approximation.blocks.iter().map(|block| block.hashes).sum();
+        let seconds = approximation.elapsed.as_secs_f64();
+        Summary {
+            blocks: errors.len(),
+            total_error,
+            mean_error: total_error / errors.len().max(1) as f64,
+            min_error: percentile(&errors, 0.0),
+            median_error: percentile(&errors, 0.5),
+            p90_error: percentile(&errors, 0.9),
+            p99_error: percentile(&errors, 0.99),
+            max_error: percentile(&errors, 1.0),
+            hashes,
+            seconds,
+            hashes_per_second: if seconds > 0.0 {
+                hashes as f64 / seconds
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Names and values of the statistics in a fixed order
+    fn fields(&self) -> [(&'static str, f64); 11] {
+        [
+            ("blocks", self.blocks as f64),
+            ("total_error", self.total_error),
+            ("mean_error", self.mean_error),
+            ("min_error", self.min_error),
+            ("median_error", self.median_error),
+            ("p90_error", self.p90_error),
+            ("p99_error", self.p99_error),
+            ("max_error", self.max_error),
+            ("hashes", self.hashes as f64),
+            ("seconds", self.seconds),
+            ("hashes_per_second", self.hashes_per_second),
+        ]
+    }
+}
+
+/// Nearest-rank percentile of sorted values, `fraction` is between 0 and 1
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (fraction * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Writes the summary and one entry per block, as JSON if the path ends in
+/// `.json` and as CSV otherwise
+pub fn write_report(path: &Path, approximation: &Approximation) -> Result<()> {
+    let summary = Summary::new(approximation);
+    let json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let content = if json {
+        to_json(&summary, &approximation.blocks)
+    } else {
+        to_csv(&summary, &approximation.blocks)
+    };
+    fs::write(path, content).map_err(|e| Error::io(path, e))
+}
+
+/// CSV with one row per block, the summary is written as comment lines at
+/// the top
+fn to_csv(summary: &Summary, blocks: &[BlockResult]) -> String {
+    let mut content = String::new();
+    for (name, value) in summary.fields() {
+        content += &format!("# {name} {value}\n");
+    }
+    content += "channel,x,y,error,found_at,hashes,counter,nonce\n";
+    for block in blocks {
+        let nonce = block
+            .nonce
+            .map(|nonce| nonce.to_string())
+            .unwrap_or_default();
+        content += &format!(
+            "{},{},{},{},{},{},{},{nonce}\n",
+            block.channel,
+            block.x,
+            block.y,
+            block.error,
+            block.found_at,
+            block.hashes,
+            block.counter
+        );
+    }
+    content
+}
+
+fn to_json(summary: &Summary, blocks: &[BlockResult]) -> String {
+    let summary: Vec<_> = summary
+        .fields()
+        .iter()
+        .map(|(name, value)| format!("\"{name}\": {}", json_number(*value)))
+        .collect();
+    let blocks: Vec<_> = blocks
+        .iter()
+        .map(|block| {
+            let nonce = block
+                .nonce
+                .map_or_else(|| "null".to_string(), |nonce| nonce.to_string());
+            format!(
+                "    {{\"channel\": {}, \"x\": {}, \"y\": {}, \"error\": {}, \"found_at\": {}, \"hashes\": {}, \"counter\": {}, \"nonce\": {nonce}}}",
+                block.channel,
+                block.x,
+                block.y,
+                json_number(f64::from(block.error)),
+                block.found_at,
+                block.hashes,
+                block.counter
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"summary\": {{{}}},\n  \"blocks\": [\n{}\n  ]\n}}\n",
+        summary.join(", "),
+        blocks.join(",\n")
+    )
+}
+
+/// JSON has no representation for infinity and NaN
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentile() {
+        let sorted: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 0.5), 5.0);
+        assert_eq!(percentile(&sorted, 0.9), 9.0);
+        assert_eq!(percentile(&sorted, 0.99), 10.0);
+        assert_eq!(percentile(&sorted, 1.0), 10.0);
+        assert_eq!(percentile(&[], 0.5), 0.0);
+    }
+
+    #[test]
+    fn non_finite_json_numbers() {
+        assert_eq!(json_number(2.5), "2.5");
+        assert_eq!(json_number(f64::INFINITY), "null");
+        assert_eq!(json_number(f64::NAN), "null");
+        let block = BlockResult {
+            channel: 0,
+            x: 8,
+            y: 4,
+            error: f32::INFINITY,
+            counter: 3,
+            found_at: 2,
+            hashes: 3,
+            nonce: None,
+        };
+        let summary = Summary {
+            blocks: 1,
+            total_error: f64::INFINITY,
+            mean_error: f64::INFINITY,
+            min_error: f64::INFINITY,
+            median_error: f64::INFINITY,
+            p90_error: f64::INFINITY,
+            p99_error: f64::INFINITY,
+            max_error: f64::INFINITY,
+            hashes: 3,
+            seconds: 0.0,
+            hashes_per_second: 0.0,
+        };
+        let json = to_json(&summary, &[block]);
+        assert!(json.contains("\"total_error\": null"));
+        assert!(json.contains(
+            "{\"channel\": 0, \"x\": 8, \"y\": 4, \"error\": null, \"found_at\": 2, \"hashes\": 3, \"counter\": 3, \"nonce\": null}"
+        ));
+    }
+}
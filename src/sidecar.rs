@@ -0,0 +1,74 @@
+//! Text files with one or more values per block, one block per line:
+//! `channel x y value...`. Lines starting with `#` are comments.
+
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Identifies a block by colour channel and pixel position of its top left
+/// corner
+pub type BlockKey = (usize, u32, u32);
+
+/// Reads a file written by [`write`], every block has at least one value
+pub fn read(path: &Path) -> Result<HashMap<BlockKey, Vec<u64>>> {
+    let invalid = |line: &str| {
+        let error = io::Error::new(io::ErrorKind::InvalidData, format!("invalid line '{line}'"));
+        Error::io(path, error)
+    };
+    let mut values = HashMap::new();
+    let content = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    for line in content.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<u64> = line
+            .split_whitespace()
+            .map(|field| field.parse().ok())
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid(line))?;
+        let [channel, x, y, ref value @ ..] = fields[..] else {
+            return Err(invalid(line));
+        };
+        if value.is_empty() {
+            return Err(invalid(line));
+        }
+        values.insert((channel as usize, x as u32, y as u32), value.to_vec());
+    }
+    Ok(values)
+}
+
+/// Writes the values of every block, `names` describe the values in the
+/// header
+pub fn write<const N: usize>(
+    path: &Path,
+    names: [&str; N],
+    values: impl IntoIterator<Item = (BlockKey, [u64; N])>,
+) -> Result<()> {
+    let mut content = format!("# channel x y {}\n", names.join(" "));
+    for ((channel, x, y), value) in values {
+        let value = value.map(|value| value.to_string()).join(" ");
+        content += &format!("{channel} {x} {y} {value}\n");
+    }
+    fs::write(path, content).map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_read_round_trip() {
+        let values = [((0, 0, 0), [1, 0]), ((2, 64, 8), [u64::MAX, 42])];
+        let path = std::env::temp_dir().join(format!("hash_art_{}.sidecar", std::process::id()));
+        write(&path, ["counter", "found"], values).unwrap();
+        let read = read(&path);
+        fs::remove_file(&path).unwrap();
+        let expected = values
+            .into_iter()
+            .map(|(key, value)| (key, value.to_vec()))
+            .collect();
+        assert_eq!(read.unwrap(), expected);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::block::{Block, BlockSize};
+use crate::color::ColorMode;
+use crate::edge::EdgePolicy;
+use crate::hash::BlockHasher;
+use crate::nonce::{with_nonce, Nonces};
+use crate::GrayscaleImage;
+use image::{DynamicImage, Luma};
+
+/// A block whose hash does not match the result target
+#[derive(Debug)]
+pub struct Mismatch {
+    /// Colour channel of the block, always 0 for grayscale images
+    pub channel: usize,
+    /// Pixel position of the top left corner of the block
+    pub x: u32,
+    pub y: u32,
+    /// Number of pixels in the block that differ from the result target
+    pub differing_pixels: usize,
+}
+
+/// Hashes every block of every plane of `source`. Colour mode, block size
+/// and edge policy must be the ones used to create the result source, pixels
+/// that are not part of a processed block are copied unchanged. Blocks with
+/// an entry in `nonces` are hashed with their nonce appended.
+pub fn hash_image(
+    source: &DynamicImage,
+    color_mode: ColorMode,
+    block_size: BlockSize,
+    edge_policy: EdgePolicy,
+    hasher: &dyn BlockHasher,
+    nonces: Option<&Nonces>,
+) -> DynamicImage {
+    let planes = color_mode
+        .split(source)
+        .iter()
+        .enumerate()
+        .map(|(channel, plane)| hash_plane(channel, plane, block_size, edge_policy, hasher, nonces))
+        .collect();
+    color_mode.merge(planes)
+}
+
+/// Compares the hashed image block by block and plane by plane against the
+/// result target
+pub fn compare(
+    hashed: &DynamicImage,
+    target: &DynamicImage,
+    color_mode: ColorMode,
+    block_size: BlockSize,
+    edge_policy: EdgePolicy,
+) -> Vec<Mismatch> {
+    let hashed = color_mode.split(hashed);
+    let target = color_mode.split(target);
+    hashed
+        .iter()
+        .zip(&target)
+        .enumerate()
+        .flat_map(|(channel, (hashed, target))| {
+            compare_plane(channel, hashed, target, block_size, edge_policy)
+        })
+        .collect()
+}
+
+fn hash_plane(
+    channel: usize,
+    source: &GrayscaleImage,
+    block_size: BlockSize,
+    edge_policy: EdgePolicy,
+    hasher: &dyn BlockHasher,
+    nonces: Option<&Nonces>,
+) -> GrayscaleImage {
+    let mut result = source.clone();
+    for block in edge_policy.blocks(source.dimensions(), block_size) {
+        let pixels = image::imageops::crop_imm(source, block.x, block.y, block.width, block.height)
+            .to_image();
+        let mut output = vec![0; pixels.len()];
+        match nonces.and_then(|nonces| nonces.get(&(channel, block.x, block.y))) {
+            Some(&nonce) => hasher.hash(&with_nonce(pixels.as_raw(), nonce), &mut output),
+            None => hasher.hash(pixels.as_raw(), &mut output),
+        }
+        for (i, value) in output.iter().enumerate() {
+            let (m, n) = (i as u32 % block.width, i as u32 / block.width);
+            result.put_pixel(block.x + m, block.y + n, Luma([*value]));
+        }
+    }
+    result
+}
+
+fn compare_plane(
+    channel: usize,
+    hashed: &GrayscaleImage,
+    target: &GrayscaleImage,
+    block_size: BlockSize,
+    edge_policy: EdgePolicy,
+) -> Vec<Mismatch> {
+    edge_policy
+        .blocks(hashed.dimensions(), block_size)
+        .into_iter()
+        .filter_map(|block| {
+            let Block { x, y, .. } = block;
+            let differing_pixels = (0..block.height)
+                .flat_map(|n| (0..block.width).map(move |m| (x + m, y + n)))
+                .filter(|&(px, py)| hashed.get_pixel(px, py) != target.get_pixel(px, py))
+                .count();
+            (differing_pixels > 0).then_some(Mismatch {
+                channel,
+                x,
+                y,
+                differing_pixels,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GrayImage;
+
+    #[test]
+    fn compare_reports_mismatching_blocks() {
+        let target = GrayImage::from_pixel(5, 3, Luma([10]));
+        let mut hashed = target.clone();
+        // Two pixels of the block at (2, 0) and one of the partial block at
+        // (4, 2)
+        hashed.put_pixel(2, 1, Luma([11]));
+        hashed.put_pixel(3, 1, Luma([11]));
+        hashed.put_pixel(4, 2, Luma([11]));
+        let mismatches = compare(
+            &DynamicImage::ImageLuma8(hashed),
+            &DynamicImage::ImageLuma8(target),
+            ColorMode::Gray,
+            BlockSize::new(2, 2),
+            EdgePolicy::Partial,
+        );
+        let found: Vec<_> = mismatches
+            .iter()
+            .map(|mismatch| {
+                (
+                    mismatch.channel,
+                    mismatch.x,
+                    mismatch.y,
+                    mismatch.differing_pixels,
+                )
+            })
+            .collect();
+        assert_eq!(found, [(0, 2, 0, 2), (0, 4, 2, 1)]);
+    }
+}